@@ -0,0 +1,283 @@
+//! Arbitrary output variables: what the camera rays hit, written next to the rendered
+//! image for debugging and for denoisers.
+//!
+//! [`crate::render::render_passes`] collects them from the camera rays of the image and
+//! averages them like its pixels, so edges line up. `.exr` and `.pfm` outputs keep the
+//! raw values as 32 bit floats, like the distance in world units or the number of octree
+//! steps. `.hdr` shares one exponent between the channels and only keeps about 3 digits,
+//! so it gets material IDs split into bytes. Every other format gets a displayable
+//! version.
+
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use image::{ImageFormat, ImageResult};
+
+use crate::{
+    material::{emission_strength_from_u8, VoxelMaterial},
+    math::Vec3,
+    octree::CastHit,
+    output::{self, linear_to_srgb, ToneMapper, ToneOperator},
+    scene::Scene,
+};
+
+/// A pass describing the surface seen by the camera. Rays which hit nothing give 0,
+/// except for `Emission` which sees the environment and `Steps`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aov {
+    /// Distance along the viewing direction of the camera.
+    Depth,
+    /// World space normal of the face the ray entered through.
+    Normal,
+    /// Linear color of the material.
+    Albedo,
+    /// Radiance emitted towards the camera by lights and the environment.
+    Emission,
+    /// Number of octree nodes the ray visited, shown as a heatmap.
+    Steps,
+    /// [`VoxelMaterial::id`] of the material, shown in false colors. Pixels take the
+    /// ID of their most central sample instead of an average.
+    MaterialId,
+}
+
+impl Aov {
+    /// Every pass, in the order `--aov all` writes them.
+    pub const ALL: [Aov; 6] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::Emission,
+        Aov::Steps,
+        Aov::MaterialId,
+    ];
+
+    /// Name used by `--aov` and added to the file name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Emission => "emission",
+            Aov::Steps => "steps",
+            Aov::MaterialId => "material",
+        }
+    }
+
+    /// Whether the samples of a pixel are averaged, otherwise the pixel gets the sample
+    /// with the largest filter weight.
+    pub fn is_filtered(&self) -> bool {
+        *self != Aov::MaterialId
+    }
+
+    /// Value of the pass for a camera ray travelling along `dir`, `forward` is the
+    /// viewing direction of the camera.
+    pub fn sample(
+        &self,
+        hit: &CastHit<VoxelMaterial>,
+        dir: &Vec3,
+        forward: &Vec3,
+        scene: &Scene,
+    ) -> Vec3 {
+        let zero = Vec3::new(0., 0., 0.);
+        let gray = |v: f64| Vec3::new(v, v, v);
+        match (self, hit.voxel) {
+            (Aov::Steps, _) => gray(hit.steps as f64),
+            (Aov::Emission, None) => scene.environment.radiance(dir),
+            (_, None) => zero,
+            (Aov::Depth, Some(_)) => gray(hit.distance * dir.dot(forward)),
+            (Aov::Normal, Some(_)) => hit.normal,
+            (Aov::Albedo, Some(voxel)) => voxel.color(),
+            (Aov::Emission, Some(voxel)) => match *voxel {
+                VoxelMaterial::Emission { emission, .. } => {
+                    voxel.color().mulf(emission_strength_from_u8(emission))
+                }
+                _ => zero,
+            },
+            (Aov::MaterialId, Some(voxel)) => gray(voxel.id() as f64),
+        }
+    }
+
+    /// Path of the pass next to the image at `output`, `out.png` becomes
+    /// `out.depth.png`.
+    pub fn path(&self, output: &Path) -> PathBuf {
+        let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match output.extension() {
+            Some(extension) => format!("{stem}.{}.{}", self.name(), extension.to_string_lossy()),
+            None => format!("{stem}.{}", self.name()),
+        };
+        output.with_file_name(name)
+    }
+
+    /// Maps the raw values of a whole pass to display colors in [0, 1]. Depth and steps
+    /// are scaled by their maximum in the image, near surfaces are bright and the
+    /// farthest one is still brighter than no surface.
+    pub fn display(&self, data: &[Vec3], tone_mapper: &ToneMapper) -> Vec<Vec3> {
+        let max = data.iter().fold(0_f64, |max, c| max.max(c.x));
+        let scale = if max > 0. { 1. / max } else { 0. };
+        let encode = |c: &Vec3| {
+            let encode = |x: f64| linear_to_srgb(x.clamp(0., 1.));
+            Vec3::new(encode(c.x), encode(c.y), encode(c.z))
+        };
+        data.iter()
+            .map(|c| match self {
+                Aov::Depth if c.x > 0. => Vec3::new(1., 1., 1.).mulf(1. - 0.9 * c.x * scale),
+                Aov::Depth => Vec3::new(0., 0., 0.),
+                Aov::Normal => c.mulf(0.5).add(&Vec3::new(0.5, 0.5, 0.5)),
+                Aov::Albedo => encode(c),
+                Aov::Emission => tone_mapper.map(c),
+                Aov::Steps => heat(c.x * scale),
+                Aov::MaterialId => {
+                    let id = c.x as u32;
+                    let channel = |shift: u32| ((id >> shift) & 0xff) as f64 / 255.;
+                    Vec3::new(channel(16), channel(8), channel(0))
+                }
+            })
+            .collect()
+    }
+
+    /// Saves a pass to `path`, see [`output::save`]. HDR formats get the raw values,
+    /// except for material IDs in `.hdr` files: red, green and blue hold bits 16 to 23,
+    /// 8 to 15 and 0 to 7 of the ID, which survive its shared exponent. Every other
+    /// format gets the [`Aov::display`] colors.
+    pub fn save(
+        &self,
+        data: &[Vec3],
+        w: u32,
+        h: u32,
+        tone_mapper: &ToneMapper,
+        path: &Path,
+    ) -> ImageResult<()> {
+        let is_radiance = ImageFormat::from_path(path).ok() == Some(ImageFormat::Hdr);
+        if *self == Aov::MaterialId && is_radiance {
+            let bytes: Vec<Vec3> = data
+                .iter()
+                .map(|c| {
+                    let id = c.x as u32;
+                    let byte = |shift: u32| ((id >> shift) & 0xff) as f64;
+                    Vec3::new(byte(16), byte(8), byte(0))
+                })
+                .collect();
+            return output::save(&bytes, w, h, tone_mapper, path);
+        }
+        if output::is_hdr(path) {
+            return output::save(data, w, h, tone_mapper, path);
+        }
+        // the display colors are already encoded, they only need to be quantized
+        let quantize = ToneMapper {
+            gamma: Some(1.),
+            ..ToneMapper::new(ToneOperator::Clamp)
+        };
+        output::save(&self.display(data, tone_mapper), w, h, &quantize, path)
+    }
+}
+
+// Heatmap from blue over green to red for t in [0, 1].
+fn heat(t: f64) -> Vec3 {
+    let stops = [
+        Vec3::new(0., 0., 0.5),
+        Vec3::new(0., 0.5, 1.),
+        Vec3::new(0., 1., 0.),
+        Vec3::new(1., 1., 0.),
+        Vec3::new(1., 0., 0.),
+    ];
+    let t = t.clamp(0., 1.) * (stops.len() - 1) as f64;
+    let i = (t as usize).min(stops.len() - 2);
+    stops[i].mixf(&stops[i + 1], t - i as f64)
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Aov::ALL
+            .into_iter()
+            .find(|aov| aov.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown AOV `{s}`, expected depth, normal, albedo, emission, steps or material"
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::assert_close;
+
+    #[test]
+    fn passes_are_saved_next_to_the_image() {
+        assert_eq!(
+            Aov::Depth.path(Path::new("out.png")),
+            Path::new("out.depth.png")
+        );
+        assert_eq!(
+            Aov::MaterialId.path(Path::new("renders/a.b.exr")),
+            Path::new("renders/a.b.material.exr")
+        );
+        assert_eq!(Aov::Steps.path(Path::new("raw")), Path::new("raw.steps"));
+        for aov in Aov::ALL {
+            assert_eq!(aov.name().parse(), Ok(aov));
+        }
+        assert!("mask".parse::<Aov>().is_err());
+    }
+
+    #[test]
+    fn display_scales_by_the_image() {
+        let gray = |v: f64| Vec3::new(v, v, v);
+        let data = [gray(0.), gray(2.), gray(4.)];
+        let depth = Aov::Depth.display(&data, &ToneMapper::default());
+        assert_close(&depth[0], &gray(0.));
+        assert_close(&depth[1], &gray(0.55));
+        assert_close(&depth[2], &gray(0.1));
+        let steps = Aov::Steps.display(&data, &ToneMapper::default());
+        assert_close(&steps[0], &Vec3::new(0., 0., 0.5));
+        assert_close(&steps[1], &Vec3::new(0., 1., 0.));
+        assert_close(&steps[2], &Vec3::new(1., 0., 0.));
+    }
+
+    #[test]
+    fn material_ids_survive_hdr_formats() {
+        let ids = [1, 0xabcdef, 0xffffff, 0x26bb29, 0x010001];
+        let data: Vec<Vec3> = ids
+            .iter()
+            .map(|&id| Vec3::new(1., 1., 1.).mulf(id as f64))
+            .collect();
+        let dir = std::env::temp_dir().join(format!("rustree-aov-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["out.exr", "out.pfm", "out.hdr"] {
+            let path = dir.join(name);
+            let tone_mapper = ToneMapper::default();
+            Aov::MaterialId
+                .save(&data, 5, 1, &tone_mapper, &path)
+                .unwrap();
+            let pixels: Vec<[f32; 3]> = if name == "out.pfm" {
+                // a single row, so the bottom row first doesn't matter
+                let bytes = std::fs::read(&path).unwrap();
+                let header = b"PF\n5 1\n-1.0\n";
+                assert_eq!(&bytes[..header.len()], header);
+                bytes[header.len()..]
+                    .chunks(12)
+                    .map(|p| [0, 4, 8].map(|i| f32::from_le_bytes(p[i..i + 4].try_into().unwrap())))
+                    .collect()
+            } else {
+                output::open_linear(&path)
+                    .unwrap()
+                    .pixels()
+                    .map(|p| p.0)
+                    .collect()
+            };
+            let loaded: Vec<u32> = pixels
+                .iter()
+                .map(|p| match name {
+                    "out.hdr" => (p[0] as u32) << 16 | (p[1] as u32) << 8 | p[2] as u32,
+                    _ => p[0] as u32,
+                })
+                .collect();
+            assert_eq!(loaded, ids, "{name}");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
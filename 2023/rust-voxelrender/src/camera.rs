@@ -0,0 +1,156 @@
+//! Camera model generating primary rays.
+
+use crate::math::Vec3;
+
+/// How camera rays are spread over the image plane.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Rays fan out from the camera position.
+    Perspective {
+        /// Vertical field of view in degrees.
+        fov: f64,
+    },
+    /// Parallel rays, nothing gets smaller with the distance.
+    Orthographic {
+        /// Height of the visible area in world units.
+        height: f64,
+    },
+}
+
+/// A camera looking from `position` to `target`.
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    /// Where the camera looks from.
+    pub position: Vec3,
+    /// Point in the center of the image.
+    pub target: Vec3,
+    /// Direction which is up in the image, it doesn't have to be orthogonal to the view.
+    pub up: Vec3,
+    /// How the rays spread over the image.
+    pub projection: Projection,
+    /// width / height of the image plane
+    pub aspect: f64,
+}
+
+/// The world is z-down: the floor is at z = 0 and everything above it has negative z.
+pub const DEFAULT_UP: Vec3 = Vec3 {
+    x: 0.,
+    y: 0.,
+    z: -1.,
+};
+
+/// 2 * atan(0.5), the field of view the renderer used before the camera was configurable
+pub const DEFAULT_FOV: f64 = 53.13010235415598;
+
+impl Camera {
+    /// Perspective camera looking from `position` at `target` with the default up
+    /// direction, field of view and a 16:9 image.
+    pub fn look_at(position: Vec3, target: Vec3) -> Self {
+        Camera {
+            position,
+            target,
+            up: DEFAULT_UP,
+            projection: Projection::Perspective { fov: DEFAULT_FOV },
+            aspect: 16. / 9.,
+        }
+    }
+
+    // Orthonormal camera basis (forward, right, up). A camera looking along `up` has no
+    // horizon, it falls back to +y or +x as up instead of producing NaN rays.
+    fn basis(&self) -> (Vec3, Vec3, Vec3) {
+        let forward = self.target.sub(&self.position).normalized();
+        let right = [self.up, Vec3::new(0., 1., 0.), Vec3::new(1., 0., 0.)]
+            .iter()
+            .map(|up| forward.cross(&up.normalized()))
+            .find(|right| right.len() > 1e-6)
+            .unwrap_or(Vec3::new(0., 1., 0.))
+            .normalized();
+        let up = right.cross(&forward);
+        (forward, right, up)
+    }
+
+    /// Direction the camera looks in.
+    pub fn forward(&self) -> Vec3 {
+        self.basis().0
+    }
+
+    /// Primary ray through the image plane position (u, v), both in [0, 1] with
+    /// (0, 0) being the top left corner of the image. Returns (origin, direction).
+    pub fn ray(&self, u: f64, v: f64) -> (Vec3, Vec3) {
+        let (forward, right, up) = self.basis();
+        let sx = 2. * u - 1.;
+        let sy = 1. - 2. * v;
+        match self.projection {
+            Projection::Perspective { fov } => {
+                let half_h = (fov.to_radians() / 2.).tan();
+                let half_w = half_h * self.aspect;
+                let dir = forward
+                    .add(&right.mulf(sx * half_w))
+                    .add(&up.mulf(sy * half_h))
+                    .normalized();
+                (self.position, dir)
+            }
+            Projection::Orthographic { height } => {
+                let half_h = height / 2.;
+                let half_w = half_h * self.aspect;
+                let origin = self
+                    .position
+                    .add(&right.mulf(sx * half_w))
+                    .add(&up.mulf(sy * half_h));
+                (origin, forward)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::assert_close;
+
+    #[test]
+    fn center_ray_points_at_target() {
+        let camera = Camera::look_at(Vec3::new(-4., 2., -3.), Vec3::new(5., -1., 0.));
+        let (origin, dir) = camera.ray(0.5, 0.5);
+        assert_close(&origin, &camera.position);
+        assert_close(&dir, &camera.target.sub(&camera.position).normalized());
+    }
+
+    #[test]
+    fn image_is_not_mirrored() {
+        // looking along +x with -z up, +y is to the right
+        let camera = Camera::look_at(Vec3::new(0., 0., 0.), Vec3::new(1., 0., 0.));
+        assert!(camera.ray(1., 0.5).1.y > 0.);
+        assert!(camera.ray(0., 0.5).1.y < 0.);
+        assert!(camera.ray(0.5, 0.).1.z < 0.);
+        assert!(camera.ray(0.5, 1.).1.z > 0.);
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let mut camera = Camera::look_at(Vec3::new(0., 0., -8.), Vec3::new(1., 1., 0.));
+        camera.projection = Projection::Orthographic { height: 4. };
+        let forward = camera.forward();
+        let (center, _) = camera.ray(0.5, 0.5);
+        for (u, v) in [(0., 0.), (1., 0.3), (0.2, 1.)] {
+            let (origin, dir) = camera.ray(u, v);
+            assert_close(&dir, &forward);
+            assert!(origin.sub(&center).dot(&forward).abs() < 1e-9);
+        }
+        let (top, _) = camera.ray(0.5, 0.);
+        assert!((top.sub(&center).len() - 2.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn looking_along_up_still_has_a_basis() {
+        // straight down with the default up of -z
+        let camera = Camera::look_at(Vec3::new(0., 0., -8.), Vec3::new(0., 0., 0.));
+        assert_close(&camera.ray(0.5, 0.5).1, &Vec3::new(0., 0., 1.));
+        for (u, v) in [(0., 0.), (1., 0.5), (0.3, 1.)] {
+            let (_, dir) = camera.ray(u, v);
+            assert!(dir.x.is_finite() && dir.y.is_finite() && dir.z.is_finite());
+            assert!(dir.z > 0.5);
+        }
+        assert!(camera.ray(0.5, 0.).1.y > 0.);
+    }
+}
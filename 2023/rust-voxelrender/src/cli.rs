@@ -0,0 +1,318 @@
+use std::{path::PathBuf, str::FromStr, thread};
+
+use rustree::{
+    aov::Aov,
+    lighting::LightLimits,
+    output::{self, ToneMapper},
+    render::{Integrator, RenderSettings},
+    sampling::PixelFilter,
+};
+
+pub const USAGE: &str = "\
+Usage: rustree [OPTIONS] [SCENE]
+
+Arguments:
+  [SCENE]                scene file to render [default: scenes/image1.scene]
+
+Options:
+  -W, --width <PX>       image width [default: 1920]
+  -H, --height <PX>      image height [default: 1080]
+  -t, --threads <N>      render threads [default: number of cores]
+  -b, --bounces <N>      maximum number of bounces per ray [default: 6]
+  -o, --output <PATH>    output image, the format follows the extension, .hdr, .exr and .pfm
+                         keep the linear radiance [default: out.png]
+      --aov <NAMES>      also write passes next to the output, comma separated: depth, normal,
+                         albedo, emission, steps, material or all, `out.png` gets
+                         `out.depth.png` and so on
+      --tonemap <NAME>   filmic, aces, reinhard, linear or clamp [default: filmic]
+      --exposure <EV>    exposure in stops before tone mapping [default: 0]
+      --white <VALUE>    radiance which becomes full white [default: depends on --tonemap]
+      --gamma <GAMMA>    encode with a plain gamma instead of the sRGB curve
+      --integrator <NAME>
+                         direct (direct light and mirrors) or path (path tracing) [default: direct]
+  -s, --samples <N>      rays per pixel, jittered for anti-aliasing [default: 1]
+      --filter <NAME>    pixel filter: box, tent or gaussian [default: tent]
+      --shadow-samples <N>
+                         shadow rays per visible face of a light, 1 aims at the face centers
+                         [default: 1]
+      --gloss-samples <N>
+                         reflection rays of glossy surfaces seen by the camera, direct
+                         integrator only [default: 4]
+      --sky-samples <N>  rays towards the sky per rough surface, direct integrator only
+                         [default: 4]
+      --light-samples <N>
+                         emissive voxels picked per shading point by their brightness, 0 uses
+                         every one which is bright enough [default: 0]
+      --light-limits <USEFUL>,<NOT_USEFUL>
+                         brightness limits deciding which lights are skipped where, replaces
+                         `light_limits` of the scene [default: 0.01,0.05]
+      --compare-culling  also render with every light and report the error of the limits
+      --seed <N>         seed of the subpixel jitter and the path tracer [default: 0]
+  -h, --help             print this help
+";
+
+pub struct Options {
+    pub scene: PathBuf,
+    pub output: PathBuf,
+    pub threads: usize,
+    /// Replaces the light limits of the scene.
+    pub light_limits: Option<LightLimits>,
+    /// Also renders with every light and reports what the light limits lose.
+    pub compare_culling: bool,
+    /// Passes written next to the output.
+    pub aovs: Vec<Aov>,
+    pub settings: RenderSettings,
+}
+
+pub enum Command {
+    Render(Box<Options>),
+    Help,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            scene: PathBuf::from("scenes/image1.scene"),
+            output: PathBuf::from("out.png"),
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            light_limits: None,
+            compare_culling: false,
+            aovs: vec![],
+            settings: RenderSettings {
+                width: 1920,
+                height: 1080,
+                bounces: 6,
+                tone_mapper: ToneMapper::default(),
+                integrator: Integrator::Direct,
+                samples: 1,
+                filter: PixelFilter::Tent,
+                shadow_samples: 1,
+                gloss_samples: 4,
+                sky_samples: 4,
+                light_samples: 0,
+                seed: 0,
+            },
+        }
+    }
+}
+
+pub fn parse<I>(args: I) -> Result<Command, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut options = Options::default();
+    let mut scene = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // support both `--width 100` and `--width=100`
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for `{flag}`"))
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-W" | "--width" => options.settings.width = positive(&flag, &value()?)?,
+            "-H" | "--height" => options.settings.height = positive(&flag, &value()?)?,
+            "-t" | "--threads" => options.threads = positive(&flag, &value()?)?,
+            "-b" | "--bounces" => options.settings.bounces = positive(&flag, &value()?)?,
+            "-o" | "--output" => options.output = PathBuf::from(value()?),
+            "--aov" => {
+                for name in value()?.split(',') {
+                    let aovs = match name {
+                        "all" => Aov::ALL.to_vec(),
+                        _ => vec![name.parse()?],
+                    };
+                    for aov in aovs {
+                        if !options.aovs.contains(&aov) {
+                            options.aovs.push(aov);
+                        }
+                    }
+                }
+            }
+            "--tonemap" => options.settings.tone_mapper.operator = value()?.parse()?,
+            "--exposure" => {
+                let value = value()?;
+                options.settings.tone_mapper.exposure = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|ev| ev.is_finite())
+                    .ok_or_else(|| format!("`{flag}` expects a number, got `{value}`"))?;
+            }
+            "--white" => options.settings.tone_mapper.white = Some(positive(&flag, &value()?)?),
+            "--gamma" => options.settings.tone_mapper.gamma = Some(positive(&flag, &value()?)?),
+            "--integrator" => options.settings.integrator = value()?.parse()?,
+            "-s" | "--samples" => options.settings.samples = positive(&flag, &value()?)?,
+            "--shadow-samples" => options.settings.shadow_samples = positive(&flag, &value()?)?,
+            "--gloss-samples" => options.settings.gloss_samples = positive(&flag, &value()?)?,
+            "--sky-samples" => options.settings.sky_samples = positive(&flag, &value()?)?,
+            "--light-samples" => {
+                let value = value()?;
+                options.settings.light_samples = value
+                    .parse()
+                    .map_err(|_| format!("`{flag}` expects a number, got `{value}`"))?;
+            }
+            "--light-limits" => {
+                let value = value()?;
+                let limits = value
+                    .split_once(',')
+                    .and_then(|(u, n)| Some((u.parse().ok()?, n.parse().ok()?)))
+                    .ok_or_else(|| {
+                        format!("`{flag}` expects <useful>,<not_useful>, got `{value}`")
+                    })?;
+                options.light_limits = Some(LightLimits::new(limits.0, limits.1)?);
+            }
+            "--compare-culling" => options.compare_culling = true,
+            "--filter" => options.settings.filter = value()?.parse()?,
+            "--seed" => {
+                let value = value()?;
+                options.settings.seed = value
+                    .parse()
+                    .map_err(|_| format!("`{flag}` expects a number, got `{value}`"))?;
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("unknown option `{arg}`"))
+            }
+            _ => {
+                if scene.is_some() {
+                    return Err(format!("unexpected argument `{arg}`"));
+                }
+                scene = Some(PathBuf::from(arg));
+            }
+        }
+    }
+    if let Some(scene) = scene {
+        options.scene = scene;
+    }
+    if !output::is_supported(&options.output) {
+        return Err(format!(
+            "unsupported output format for `{}`",
+            options.output.display()
+        ));
+    }
+    Ok(Command::Render(Box::new(options)))
+}
+
+fn positive<T>(flag: &str, value: &str) -> Result<T, String>
+where
+    T: FromStr + Default + PartialOrd,
+{
+    match value.parse::<T>() {
+        Ok(v) if v > T::default() => Ok(v),
+        _ => Err(format!("`{flag}` expects a positive number, got `{value}`")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Options, String> {
+        match parse(args.iter().map(|s| s.to_string()))? {
+            Command::Render(options) => Ok(*options),
+            Command::Help => Err("help".to_string()),
+        }
+    }
+
+    fn parse_error(args: &[&str]) -> String {
+        match parse_args(args) {
+            Ok(_) => panic!("{args:?} should not parse"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn defaults_without_arguments() {
+        let options = parse_args(&[]).unwrap();
+        assert_eq!(options.scene, Path::new("scenes/image1.scene"));
+        assert_eq!(options.output, Path::new("out.png"));
+        assert!(options.threads >= 1);
+        assert!(options.light_limits.is_none() && !options.compare_culling);
+        assert!(options.aovs.is_empty());
+        let settings = options.settings;
+        assert_eq!((settings.width, settings.height), (1920, 1080));
+        assert_eq!((settings.bounces, settings.samples), (6, 1));
+        assert_eq!(settings.tone_mapper, ToneMapper::default());
+        assert_eq!(settings.integrator, Integrator::Direct);
+        assert_eq!(settings.filter, PixelFilter::Tent);
+        assert_eq!(
+            (
+                settings.shadow_samples,
+                settings.gloss_samples,
+                settings.sky_samples
+            ),
+            (1, 4, 4)
+        );
+        assert_eq!((settings.light_samples, settings.seed), (0, 0));
+    }
+
+    #[test]
+    fn parses_options() {
+        let options = parse_args(&[
+            "-W",
+            "640",
+            "--height=360",
+            "--aov",
+            "depth,all",
+            "--light-limits",
+            "0.005,0.02",
+            "scenes/image2.scene",
+        ])
+        .unwrap();
+        assert_eq!(options.scene, Path::new("scenes/image2.scene"));
+        assert_eq!(
+            (options.settings.width, options.settings.height),
+            (640, 360)
+        );
+        assert_eq!(options.aovs, Aov::ALL);
+        assert_eq!(
+            options.light_limits,
+            Some(LightLimits::new(0.005, 0.02).unwrap())
+        );
+        assert_eq!(parse_error(&["-h"]), "help");
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert_eq!(
+            parse_error(&["--frobnicate"]),
+            "unknown option `--frobnicate`"
+        );
+        assert_eq!(parse_error(&["-x", "1"]), "unknown option `-x`");
+        assert_eq!(parse_error(&["--width"]), "missing value for `--width`");
+        assert_eq!(parse_error(&["a.scene", "-o"]), "missing value for `-o`");
+        assert_eq!(
+            parse_error(&["--width", "abc"]),
+            "`--width` expects a positive number, got `abc`"
+        );
+        assert_eq!(
+            parse_error(&["--samples=0"]),
+            "`--samples` expects a positive number, got `0`"
+        );
+        assert_eq!(
+            parse_error(&["--seed", "-1"]),
+            "`--seed` expects a number, got `-1`"
+        );
+        assert_eq!(
+            parse_error(&["--light-limits", "0.1"]),
+            "`--light-limits` expects <useful>,<not_useful>, got `0.1`"
+        );
+        assert_eq!(
+            parse_error(&["--light-limits", "0.1,inf"]),
+            "light limit inf must be a finite number"
+        );
+        assert_eq!(
+            parse_error(&["a.scene", "b.scene"]),
+            "unexpected argument `b.scene`"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Light arriving from outside of the scene, seen by rays which leave the octree.
+
+use std::{
+    f64::consts::{PI, TAU},
+    path::Path,
+};
+
+use crate::{math::Vec3, output::open_linear};
+
+/// Radiance of the environment in every direction. The world is z-down, so the sky is
+/// in the directions with negative z.
+pub enum Environment {
+    /// The same radiance from every direction.
+    Constant(Vec3),
+    /// Analytic sky blending from `horizon` to `zenith` above and to `ground` below the
+    /// horizon, optionally with a sun disc.
+    Sky {
+        /// Radiance straight up.
+        zenith: Vec3,
+        /// Radiance at the horizon.
+        horizon: Vec3,
+        /// Radiance below the horizon.
+        ground: Vec3,
+        /// Sun disc drawn on top of the gradient.
+        sun: Option<SkySun>,
+    },
+    /// Equirectangular image, the top row is straight up and the center column looks
+    /// along +x.
+    Image {
+        /// Width of the image in pixels.
+        width: usize,
+        /// Height of the image in pixels.
+        height: usize,
+        /// Linear radiance row by row, starting at the top.
+        pixels: Vec<Vec3>,
+    },
+}
+
+/// Sun disc drawn into an analytic sky.
+pub struct SkySun {
+    /// Direction towards the sun.
+    pub direction: Vec3,
+    /// Angular radius of the disc in radians.
+    pub radius: f64,
+    /// Radiance of the disc.
+    pub radiance: Vec3,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment::Constant(Vec3::new(0., 0., 0.))
+    }
+}
+
+impl Environment {
+    /// Loads an equirectangular image, `.hdr` and `.exr` files keep their linear
+    /// radiance, every other format is used as is. The radiance is scaled by `strength`.
+    pub fn load_image(path: &Path, strength: f64) -> Result<Self, image::ImageError> {
+        let image = open_linear(path)?;
+        let pixels = image
+            .pixels()
+            .map(|p| Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64).mulf(strength))
+            .collect();
+        Ok(Environment::Image {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            pixels,
+        })
+    }
+
+    /// Whether no light comes from the environment at all, so it doesn't need to be
+    /// sampled.
+    pub fn is_black(&self) -> bool {
+        match self {
+            Environment::Constant(c) => c.x <= 0. && c.y <= 0. && c.z <= 0.,
+            _ => false,
+        }
+    }
+
+    /// Radiance arriving from the direction `dir` (pointing away from the scene).
+    pub fn radiance(&self, dir: &Vec3) -> Vec3 {
+        match self {
+            Environment::Constant(c) => *c,
+            Environment::Sky {
+                zenith,
+                horizon,
+                ground,
+                sun,
+            } => {
+                let up = -dir.z;
+                let sky = if up >= 0. {
+                    horizon.mixf(zenith, up.sqrt())
+                } else {
+                    horizon.mixf(ground, (-up).sqrt())
+                };
+                match sun {
+                    Some(sun) if dir.dot(&sun.direction) >= sun.radius.cos() => {
+                        sky.add(&sun.radiance)
+                    }
+                    _ => sky,
+                }
+            }
+            Environment::Image {
+                width,
+                height,
+                pixels,
+            } => {
+                let u = 0.5 + dir.y.atan2(dir.x) / TAU;
+                let v = (-dir.z).clamp(-1., 1.).acos() / PI;
+                let x = ((u * *width as f64) as usize).min(width - 1);
+                let y = ((v * *height as f64) as usize).min(height - 1);
+                pixels[y * width + x]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn sky_blends_towards_zenith_and_ground() {
+        let sky = Environment::Sky {
+            zenith: Vec3::new(0., 0., 1.),
+            horizon: Vec3::new(1., 1., 1.),
+            ground: Vec3::new(0., 1., 0.),
+            sun: Some(SkySun {
+                direction: Vec3::new(1., 0., -1.).normalized(),
+                radius: 0.05,
+                radiance: Vec3::new(10., 10., 10.),
+            }),
+        };
+        let up = Vec3::new(0., 0., -1.);
+        assert_close(&sky.radiance(&up), &Vec3::new(0., 0., 1.));
+        assert_close(
+            &sky.radiance(&Vec3::new(0., 1., 0.)),
+            &Vec3::new(1., 1., 1.),
+        );
+        assert_close(&sky.radiance(&up.mulf(-1.)), &Vec3::new(0., 1., 0.));
+        let towards_sun = Vec3::new(1., 0., -1.).normalized();
+        assert!(sky.radiance(&towards_sun).x > 10.);
+        let beside_sun = Vec3::new(1., 0.2, -1.).normalized();
+        assert!(sky.radiance(&beside_sun).x < 1.);
+    }
+
+    #[test]
+    fn image_is_equirectangular() {
+        // 4x2 image, each pixel has its own color
+        let pixels = (0..8).map(|i| Vec3::new(i as f64, 0., 0.)).collect();
+        let env = Environment::Image {
+            width: 4,
+            height: 2,
+            pixels,
+        };
+        let r = |x: f64, y: f64, z: f64| env.radiance(&Vec3::new(x, y, z).normalized()).x;
+        // upper half above the horizon, +x in the center, then +y
+        assert_eq!(r(1., 0., -0.5), 2.);
+        assert_eq!(r(0., 1., -0.5), 3.);
+        assert_eq!(r(-1., -0.01, -0.5), 0.);
+        assert_eq!(r(0., -1., -0.5), 1.);
+        assert_eq!(r(1., 0., 0.5), 6.);
+        assert_eq!(r(0., 0., 1.), 6.);
+    }
+}
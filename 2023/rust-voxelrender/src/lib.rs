@@ -0,0 +1,24 @@
+//! Sparse voxel octree renderer.
+//!
+//! Voxels are stored in an [`octree::Octree`], lights in a [`lighting::LightingTree`]
+//! and both are usually built from a text [`scene::Scene`]. [`render::render_image`]
+//! traces the scene through a [`camera::Camera`] and [`output`] turns the result into
+//! an image file,
+//! [`render::render_passes`] adds passes like depth and normals for debugging and denoising.
+
+#![warn(missing_docs)]
+
+pub mod aov;
+pub mod camera;
+pub mod environment;
+pub mod lighting;
+pub mod material;
+pub mod math;
+pub mod octree;
+pub mod output;
+pub mod render;
+pub mod sampling;
+pub mod scene;
+
+#[cfg(test)]
+mod test_util;
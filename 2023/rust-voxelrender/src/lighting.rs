@@ -0,0 +1,643 @@
+//! Spatial lookup of the lights which can noticeably illuminate a point.
+
+use std::{collections::HashMap, f64::consts::TAU};
+
+use crate::{
+    material::emission_strength_from_u8,
+    math::{Cube, Vec3},
+    sampling::{orthonormal_basis, stratified, Rng},
+};
+
+/// Octree of light positions. A light is stored in the largest node in which it
+/// illuminates every point by more than [`LightLimits::useful`] and left out of nodes in
+/// which it can't reach [`LightLimits::not_useful`] anywhere.
+/// Lights which are not made of voxels are few, they are kept in a plain list and
+/// reach every point.
+pub struct LightingTree {
+    root: LightingNode,
+    limits: LightLimits,
+    // emission of every emissive voxel, which decides the nodes it is stored in
+    emitters: HashMap<[i64; 3], u8>,
+    analytic: Vec<Light>,
+}
+
+struct LightingNode {
+    split: Option<Box<[LightingNode; 8]>>,
+    bounds: Cube,
+    // position and emission
+    lights: Vec<(Vec3, u8)>,
+}
+
+/// Light which is not made of emissive voxels. These lights are not visible themselves,
+/// they only illuminate surfaces.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Light {
+    /// Light from a disc in the sky with the angular radius `radius` (in radians) around
+    /// `direction`, which points towards the sun. `irradiance` arrives at a surface
+    /// facing the sun.
+    Sun {
+        /// Direction towards the sun.
+        direction: Vec3,
+        /// Angular radius of the disc in radians.
+        radius: f64,
+        /// Irradiance on a surface facing the sun.
+        irradiance: Vec3,
+    },
+    /// Light from a single point, falling off with the squared distance.
+    Point {
+        /// Where the light is.
+        position: Vec3,
+        /// Irradiance at a distance of 1 facing the light.
+        intensity: Vec3,
+    },
+    /// Point light shining along `direction`. The light is at full intensity up to the
+    /// angle `inner` from the direction and fades out smoothly until `outer`.
+    Spot {
+        /// Where the light is.
+        position: Vec3,
+        /// Direction the cone points to.
+        direction: Vec3,
+        /// Angle from `direction` up to which the light is at full intensity, in radians.
+        inner: f64,
+        /// Angle from `direction` at which the light has faded out, in radians.
+        outer: f64,
+        /// Intensity in the center of the cone.
+        intensity: Vec3,
+    },
+}
+
+/// Direction towards a light from a shading point and the light arriving along it.
+pub struct LightSample {
+    /// Unit direction towards the light.
+    pub dir: Vec3,
+    /// Distance to the light, infinite for the sun.
+    pub distance: f64,
+    /// Irradiance on a surface facing the light.
+    pub irradiance: Vec3,
+}
+
+impl Light {
+    /// Sample `i` out of `n` of the light arriving at `position`. Point and spot lights
+    /// always give the same sample, the sun is sampled stratified over its disc with
+    /// the center for a single sample. Every sample carries the full irradiance, so the
+    /// samples have to be averaged.
+    pub fn sample(&self, position: &Vec3, i: usize, n: usize, rng: &mut Rng) -> LightSample {
+        match *self {
+            Light::Sun {
+                direction,
+                radius,
+                irradiance,
+            } => {
+                let dir = if n == 1 {
+                    direction
+                } else {
+                    // uniform over the cone of directions
+                    let (u, v) = stratified(i, n, rng);
+                    let cos = 1. - u * (1. - radius.cos());
+                    let sin = (1. - cos * cos).max(0.).sqrt();
+                    let angle = v * TAU;
+                    let (b1, b2) = orthonormal_basis(&direction);
+                    b1.mulf(sin * angle.cos())
+                        .add(&b2.mulf(sin * angle.sin()))
+                        .add(&direction.mulf(cos))
+                };
+                LightSample {
+                    dir,
+                    distance: f64::INFINITY,
+                    irradiance,
+                }
+            }
+            Light::Point {
+                position: light,
+                intensity,
+            } => point_sample(position, &light, intensity),
+            Light::Spot {
+                position: light,
+                direction,
+                inner,
+                outer,
+                intensity,
+            } => {
+                let mut sample = point_sample(position, &light, intensity);
+                let cos = -sample.dir.dot(&direction);
+                let (cos_inner, cos_outer) = (inner.cos(), outer.cos());
+                let t = if cos_inner > cos_outer {
+                    ((cos - cos_outer) / (cos_inner - cos_outer)).clamp(0., 1.)
+                } else if cos >= cos_outer {
+                    1.
+                } else {
+                    0.
+                };
+                sample.irradiance = sample.irradiance.mulf(t * t * (3. - 2. * t));
+                sample
+            }
+        }
+    }
+}
+
+fn point_sample(position: &Vec3, light: &Vec3, intensity: Vec3) -> LightSample {
+    let to_light = light.sub(position);
+    let distance = to_light.len();
+    LightSample {
+        dir: to_light.mulf(1. / distance),
+        distance,
+        irradiance: intensity.mulf(1. / (distance * distance)),
+    }
+}
+
+/// Default of [`LightLimits::useful`].
+pub const USEFULL_LIGHT_LIMIT: f64 = 1. / 100.;
+/// Default of [`LightLimits::not_useful`].
+pub const NOT_USEFULL_LIGHT_LIMIT: f64 = 1. / 20.;
+
+/// Brightness (emission strength over squared distance) limits which decide where the
+/// lighting tree keeps a light. Lower limits keep more lights and lose less energy, but
+/// every kept light costs shadow rays.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightLimits {
+    /// A node keeps the light itself if it is brighter than this everywhere in the node.
+    pub useful: f64,
+    /// A node drops the light if it can't be brighter than this anywhere in the node.
+    pub not_useful: f64,
+}
+
+impl LightLimits {
+    /// Limits which must be finite and not negative.
+    pub fn new(useful: f64, not_useful: f64) -> Result<Self, String> {
+        for limit in [useful, not_useful] {
+            if !limit.is_finite() {
+                return Err(format!("light limit {limit} must be a finite number"));
+            }
+            if limit < 0. {
+                return Err(format!("light limit {limit} must not be negative"));
+            }
+        }
+        Ok(LightLimits { useful, not_useful })
+    }
+
+    /// Keeps every light everywhere.
+    pub const BRUTE_FORCE: LightLimits = LightLimits {
+        useful: 0.,
+        not_useful: 0.,
+    };
+}
+
+impl Default for LightLimits {
+    fn default() -> Self {
+        LightLimits {
+            useful: USEFULL_LIGHT_LIMIT,
+            not_useful: NOT_USEFULL_LIGHT_LIMIT,
+        }
+    }
+}
+
+impl LightingTree {
+    /// Empty tree covering `bounds` with the default limits.
+    pub fn new(bounds: Cube) -> Self {
+        Self::with_limits(bounds, LightLimits::default())
+    }
+
+    /// Empty tree covering `bounds` which sorts its lights by `limits`.
+    pub fn with_limits(bounds: Cube, limits: LightLimits) -> Self {
+        LightingTree {
+            root: LightingNode::new(bounds),
+            limits,
+            emitters: HashMap::new(),
+            analytic: vec![],
+        }
+    }
+
+    /// Limits deciding which nodes keep a light.
+    pub fn limits(&self) -> LightLimits {
+        self.limits
+    }
+
+    /// Changes the limits and sorts every light into the tree again.
+    pub fn set_limits(&mut self, limits: LightLimits) {
+        self.limits = limits;
+        self.root = LightingNode::new(self.root.bounds);
+        // sorted, so the lights end up in the same order in every node
+        let mut emitters: Vec<_> = self.emitters.iter().collect();
+        emitters.sort();
+        for (&[x, y, z], &emission) in emitters {
+            self.root.insert(Vec3::newi(x, y, z), emission, &limits);
+        }
+    }
+
+    /// Adds a light which is not made of voxels.
+    pub fn add(&mut self, light: Light) {
+        self.analytic.push(light);
+    }
+
+    /// Every light which is not made of voxels.
+    pub fn analytic(&self) -> &[Light] {
+        &self.analytic
+    }
+
+    /// Adds the emissive voxel containing `position`, replacing the light which was there.
+    pub fn insert(&mut self, position: Vec3, emission_strength: u8) {
+        let position = voxel(&position);
+        self.remove(position);
+        self.emitters.insert(key(&position), emission_strength);
+        self.root.insert(position, emission_strength, &self.limits);
+    }
+
+    /// Removes the emissive voxel containing `position` and returns its emission, nodes
+    /// which become empty are merged again.
+    pub fn remove(&mut self, position: Vec3) -> Option<u8> {
+        let position = voxel(&position);
+        let emission_strength = self.emitters.remove(&key(&position))?;
+        self.root.remove(&position, emission_strength, &self.limits);
+        Some(emission_strength)
+    }
+
+    /// Changes the emission of the light at `position`, returns false if there is none.
+    pub fn update(&mut self, position: Vec3, emission_strength: u8) -> bool {
+        if !self.emitters.contains_key(&key(&position)) {
+            return false;
+        }
+        self.insert(position, emission_strength);
+        true
+    }
+
+    /// Removes every light from `min` to `max`, both inclusive.
+    pub fn remove_box(&mut self, min: Vec3, max: Vec3) {
+        let (min, max) = (key(&min), key(&max));
+        let inside: Vec<[i64; 3]> = self
+            .emitters
+            .keys()
+            .filter(|p| (0..3).all(|i| min[i] <= p[i] && p[i] <= max[i]))
+            .copied()
+            .collect();
+        for [x, y, z] in inside {
+            self.remove(Vec3::newi(x, y, z));
+        }
+    }
+
+    /// Emission of the light at `position`.
+    pub fn emission(&self, position: &Vec3) -> Option<u8> {
+        self.emitters.get(&key(position)).copied()
+    }
+
+    /// Number of emissive voxels.
+    pub fn len(&self) -> usize {
+        self.emitters.len()
+    }
+
+    /// Whether there are no emissive voxels.
+    pub fn is_empty(&self) -> bool {
+        self.emitters.is_empty()
+    }
+
+    /// Calls `f` with the position and emission of every light relevant for `p`.
+    pub fn query<F>(&self, p: &Vec3, mut f: F)
+    where
+        F: FnMut(&Vec3, u8),
+    {
+        self.root.query_r(p, &mut f)
+    }
+}
+
+// Voxel coordinates of a position.
+fn key(p: &Vec3) -> [i64; 3] {
+    [p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64]
+}
+
+// Corner of the voxel containing a position, which is where the tree keeps its light.
+fn voxel(p: &Vec3) -> Vec3 {
+    let [x, y, z] = key(p);
+    Vec3::newi(x, y, z)
+}
+
+impl LightingNode {
+    fn new(bounds: Cube) -> Self {
+        LightingNode {
+            split: None,
+            lights: vec![],
+            bounds,
+        }
+    }
+
+    fn insert(&mut self, position: Vec3, emission_strength: u8, limits: &LightLimits) {
+        // if minimimum possible ilumination is usefull insert (=> every voxel in this octet could be iluminated)
+        // else if maximum possible ilumination not usefull break (no child could ever be iluminated)
+        // else offer to children
+        let strength = emission_strength_from_u8(emission_strength);
+
+        let max_distance = self.bounds.max_border_dist(&position.voxel_center());
+        let min_brightness = strength / max_distance.powi(2);
+        let is_min_usefull = min_brightness > limits.useful;
+        if is_min_usefull {
+            self.lights.push((position, emission_strength));
+            return;
+        }
+
+        let is_max_usefull = if self.bounds.containsf(&position) {
+            true
+        } else {
+            (strength / self.bounds.distance_to(&position).powi(2)) > limits.not_useful
+        };
+
+        let is_single_voxel_size = self.bounds.size == 1.;
+
+        if is_single_voxel_size || !is_max_usefull {
+            return;
+        }
+
+        self.split();
+        if let Some(c) = &mut self.split {
+            c.iter_mut()
+                .for_each(|c| c.insert(position, emission_strength, limits));
+        }
+    }
+
+    // Follows the same decisions as `insert` to every node the light was stored in.
+    fn remove(&mut self, position: &Vec3, emission_strength: u8, limits: &LightLimits) {
+        let strength = emission_strength_from_u8(emission_strength);
+
+        let max_distance = self.bounds.max_border_dist(&position.voxel_center());
+        if strength / max_distance.powi(2) > limits.useful {
+            if let Some(i) = self.lights.iter().position(|(l, _)| l == position) {
+                self.lights.swap_remove(i);
+            }
+            return;
+        }
+        let is_max_usefull = self.bounds.containsf(position)
+            || strength / self.bounds.distance_to(position).powi(2) > limits.not_useful;
+        if self.bounds.size == 1. || !is_max_usefull {
+            return;
+        }
+        let Some(c) = &mut self.split else {
+            return;
+        };
+        c.iter_mut()
+            .for_each(|c| c.remove(position, emission_strength, limits));
+        if c.iter().all(|c| c.lights.is_empty() && c.split.is_none()) {
+            self.split = None;
+        }
+    }
+
+    fn split(&mut self) {
+        if self.split.is_some() {
+            return;
+        }
+        let Cube {
+            fpos: Vec3 { x, y, z },
+            size,
+        } = self.bounds;
+        let n = size / 2.;
+        let o = 0.;
+        self.split = Some(Box::new([
+            Self::new(Cube::new(x + o, y + o, z + o, n)),
+            Self::new(Cube::new(x + o, y + o, z + n, n)),
+            Self::new(Cube::new(x + o, y + n, z + o, n)),
+            Self::new(Cube::new(x + o, y + n, z + n, n)),
+            Self::new(Cube::new(x + n, y + o, z + o, n)),
+            Self::new(Cube::new(x + n, y + o, z + n, n)),
+            Self::new(Cube::new(x + n, y + n, z + o, n)),
+            Self::new(Cube::new(x + n, y + n, z + n, n)),
+        ]));
+    }
+
+    fn index_of_f(&self, p: &Vec3) -> usize {
+        let hs = self.bounds.size / 2.;
+        let mut idx = 0;
+        if p.x >= self.bounds.fpos.x + hs {
+            idx |= 0b100;
+        }
+        if p.y >= self.bounds.fpos.y + hs {
+            idx |= 0b010;
+        }
+        if p.z >= self.bounds.fpos.z + hs {
+            idx |= 0b001;
+        }
+        idx
+    }
+
+    fn query_r<F>(&self, p: &Vec3, f: &mut F)
+    where
+        F: FnMut(&Vec3, u8),
+    {
+        for (c, emission) in &self.lights {
+            f(c, *emission);
+        }
+        let index = self.index_of_f(p);
+        if let Some(c) = &self.split {
+            c[index].query_r(p, f);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{octree::OOctree, test_util::assert_close};
+
+    #[test]
+    fn octree_index_matches_lighting_tree_split() {
+        let bounds = Cube::new(-8., 4., 0., 16.);
+        let mut lighting = LightingNode::new(bounds);
+        lighting.split();
+        let octree = OOctree::<u8>::new();
+        let children = lighting.split.as_ref().unwrap();
+        for (i, child) in children.iter().enumerate() {
+            let (idx, sub) = octree.index_of(&bounds, &child.bounds.center());
+            assert_eq!(idx, i);
+            assert_eq!(sub, child.bounds);
+            assert_eq!(bounds.octant(i), child.bounds);
+            assert_eq!(lighting.index_of_f(&child.bounds.center()), i);
+        }
+    }
+
+    // positions of the lights relevant for points spread over the bounds
+    fn reach(lighting: &LightingTree) -> Vec<Vec<[i64; 3]>> {
+        let mut reach = vec![];
+        for x in (-16..16).step_by(3) {
+            for y in (-16..16).step_by(5) {
+                for z in (-16..16).step_by(7) {
+                    let mut lights = vec![];
+                    lighting.query(&Vec3::newi(x, y, z), |l, _| lights.push(key(l)));
+                    lights.sort();
+                    reach.push(lights);
+                }
+            }
+        }
+        reach
+    }
+
+    #[test]
+    fn removed_lights_leave_no_trace() {
+        let bounds = Cube::new(-16., -16., -16., 32.);
+        let kept = [(Vec3::newi(3, -4, 5), 40), (Vec3::newi(-10, 2, 0), 90)];
+        let mut expected = LightingTree::new(bounds);
+        for (p, e) in kept {
+            expected.insert(p, e);
+        }
+
+        let mut lighting = LightingTree::new(bounds);
+        lighting.insert(Vec3::newi(0, 0, 0), 10);
+        for (p, e) in kept {
+            lighting.insert(p, e);
+        }
+        lighting.insert(Vec3::newi(12, 12, -12), 120);
+        assert_eq!(lighting.remove(Vec3::newi(0, 0, 0)), Some(10));
+        assert_eq!(lighting.remove(Vec3::newi(12, 12, -12)), Some(120));
+        assert_eq!(lighting.remove(Vec3::newi(12, 12, -12)), None);
+        assert_eq!(lighting.len(), 2);
+        assert_eq!(reach(&lighting), reach(&expected));
+
+        // removing everything merges the nodes again
+        lighting.remove_box(Vec3::newi(-16, -16, -16), Vec3::newi(15, 15, 15));
+        assert!(lighting.is_empty());
+        assert!(lighting.root.lights.is_empty() && lighting.root.split.is_none());
+    }
+
+    #[test]
+    fn update_changes_reach() {
+        let bounds = Cube::new(-16., -16., -16., 32.);
+        let light = Vec3::newi(-14, -14, -14);
+        let mut weak = LightingTree::new(bounds);
+        weak.insert(light, 0);
+        let mut strong = LightingTree::new(bounds);
+        strong.insert(light, 120);
+        assert_ne!(reach(&weak), reach(&strong));
+
+        assert!(weak.update(light, 120));
+        assert_eq!(weak.emission(&light), Some(120));
+        assert_eq!(reach(&weak), reach(&strong));
+        // inserting again replaces the light instead of adding it twice
+        weak.insert(light, 120);
+        assert_eq!(reach(&weak), reach(&strong));
+        assert!(!weak.update(Vec3::newi(0, 0, 0), 10));
+    }
+
+    #[test]
+    fn positions_inside_a_voxel_refer_to_it() {
+        let bounds = Cube::new(-16., -16., -16., 32.);
+        let mut expected = LightingTree::new(bounds);
+        expected.insert(Vec3::newi(1, 2, -4), 60);
+        let mut lighting = LightingTree::new(bounds);
+        lighting.insert(Vec3::new(1.5, 2.5, -3.5), 60);
+        assert_eq!(reach(&lighting), reach(&expected));
+        assert_eq!(lighting.remove(Vec3::new(1.2, 2.2, -3.8)), Some(60));
+        assert!(lighting.is_empty());
+        assert!(reach(&lighting).iter().all(|lights| lights.is_empty()));
+        assert!(lighting.root.lights.is_empty() && lighting.root.split.is_none());
+    }
+
+    // lights of random strength at distinct positions spread over the bounds
+    fn random_lights(rng: &mut Rng) -> Vec<(Vec3, u8)> {
+        let mut lights: Vec<(Vec3, u8)> = vec![];
+        while lights.len() < 64 {
+            let mut c = || (rng.float() * 32.).floor() - 16.;
+            let p = Vec3::new(c(), c(), c());
+            if lights.iter().all(|(l, _)| l != &p) {
+                lights.push((p, (rng.float() * 160.) as u8));
+            }
+        }
+        lights
+    }
+
+    #[test]
+    fn culled_lights_are_below_the_limit() {
+        let bounds = Cube::new(-16., -16., -16., 32.);
+        let mut rng = Rng::new(3);
+        for limits in [
+            LightLimits::default(),
+            LightLimits::new(0.001, 0.005).unwrap(),
+            LightLimits::new(0.1, 0.5).unwrap(),
+        ] {
+            let mut lighting = LightingTree::with_limits(bounds, limits);
+            let lights = random_lights(&mut rng);
+            for &(p, e) in &lights {
+                lighting.insert(p, e);
+            }
+            for _ in 0..200 {
+                let mut c = || rng.float() * 32. - 16.;
+                let p = Vec3::new(c(), c(), c());
+                let mut found = vec![];
+                lighting.query(&p, |l, _| found.push(key(l)));
+                for (l, e) in &lights {
+                    if found.contains(&key(l)) {
+                        continue;
+                    }
+                    let d = l.voxel_center().sub(&p).len();
+                    let brightness = emission_strength_from_u8(*e) / (d * d);
+                    assert!(brightness <= limits.not_useful, "{limits:?} {l:?} {p:?}");
+                }
+            }
+
+            // without limits every light reaches every point
+            lighting.set_limits(LightLimits::BRUTE_FORCE);
+            let mut count = 0;
+            lighting.query(&Vec3::newi(0, 0, 0), |_, _| count += 1);
+            assert_eq!(count, lights.len());
+        }
+    }
+
+    #[test]
+    fn changed_limits_rebuild_the_tree() {
+        let bounds = Cube::new(-16., -16., -16., 32.);
+        let limits = LightLimits::new(0.05, 0.2).unwrap();
+        let lights = random_lights(&mut Rng::new(4));
+        let mut expected = LightingTree::with_limits(bounds, limits);
+        let mut lighting = LightingTree::new(bounds);
+        for &(p, e) in &lights {
+            expected.insert(p, e);
+            lighting.insert(p, e);
+        }
+        assert_ne!(reach(&lighting), reach(&expected));
+        lighting.set_limits(limits);
+        assert_eq!(lighting.limits(), limits);
+        assert_eq!(reach(&lighting), reach(&expected));
+
+        assert!(LightLimits::new(-0.1, 0.).is_err());
+        assert!(LightLimits::new(0., f64::NAN).is_err());
+    }
+
+    #[test]
+    fn spot_light_fades_between_cones() {
+        let spot = Light::Spot {
+            position: Vec3::new(0., 0., -10.),
+            direction: Vec3::new(0., 0., 1.),
+            inner: 0.2,
+            outer: 0.4,
+            intensity: Vec3::new(100., 100., 100.),
+        };
+        let mut rng = Rng::new(0);
+        let at = |x: f64, rng: &mut Rng| spot.sample(&Vec3::new(x, 0., 0.), 0, 1, rng);
+        let center = at(0., &mut rng);
+        assert_close(&center.dir, &Vec3::new(0., 0., -1.));
+        assert!((center.distance - 10.).abs() < 1e-12);
+        assert_close(&center.irradiance, &Vec3::new(1., 1., 1.));
+        // tan(0.2) * 10 = 2.03, tan(0.4) * 10 = 4.23
+        let inside = at(1.9, &mut rng).irradiance.x;
+        let fading = at(3., &mut rng).irradiance.x;
+        assert!((inside - 100. / (100. + 1.9 * 1.9)).abs() < 1e-12);
+        assert!(fading > 0. && fading < inside);
+        assert_eq!(at(4.4, &mut rng).irradiance.x, 0.);
+    }
+
+    #[test]
+    fn sun_samples_stay_inside_its_disc() {
+        let direction = Vec3::new(1., 1., -2.).normalized();
+        let sun = Light::Sun {
+            direction,
+            radius: 0.1,
+            irradiance: Vec3::new(3., 3., 3.),
+        };
+        let mut rng = Rng::new(2);
+        let mut spread = 0_f64;
+        for i in 0..16 {
+            let sample = sun.sample(&Vec3::new(0., 0., 0.), i, 16, &mut rng);
+            assert!((sample.dir.len() - 1.).abs() < 1e-9);
+            let angle = sample.dir.dot(&direction).min(1.).acos();
+            assert!(angle <= 0.1 + 1e-9);
+            spread = spread.max(angle);
+            assert!(sample.distance.is_infinite());
+        }
+        assert!(spread > 0.05);
+        let center = sun.sample(&Vec3::new(0., 0., 0.), 0, 1, &mut rng);
+        assert_close(&center.dir, &direction);
+    }
+}
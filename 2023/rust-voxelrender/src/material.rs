@@ -0,0 +1,188 @@
+//! Voxel materials.
+//!
+//! Colors are written as 8 bit sRGB, like in an image editor. Materials keep those bytes
+//! so that voxels stay small and [`VoxelMaterial::color`] decodes them to linear values
+//! through a table. All shading adds and multiplies linear radiance,
+//! [`crate::output::ToneMapper`] maps the result back to sRGB for display, while HDR
+//! outputs keep it linear. Emission strengths and light intensities scale linear
+//! colors.
+
+use std::sync::OnceLock;
+
+use crate::{math::Vec3, octree::Octree};
+
+/// 8 bit sRGB color.
+pub type Color = [u8; 3];
+
+/// Linear value of an 8 bit sRGB channel, the inverse of the sRGB transfer function.
+pub fn srgb_to_linear(c: u8) -> f64 {
+    static TABLE: OnceLock<[f64; 256]> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        std::array::from_fn(|c| {
+            let c = c as f64 / 255.;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        })
+    });
+    table[c as usize]
+}
+
+/// Linear color with every channel in [0, 1] of an 8 bit sRGB color.
+pub fn color_to_linear(c: &Color) -> Vec3 {
+    Vec3::new(
+        srgb_to_linear(c[0]),
+        srgb_to_linear(c[1]),
+        srgb_to_linear(c[2]),
+    )
+}
+
+/// Surface of a voxel. Roughness 255 is fully diffuse, anything below also reflects.
+/// Every voxel of the octree stores one, so colors stay 8 bit sRGB, see
+/// [`VoxelMaterial::color`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VoxelMaterial {
+    /// Opaque surface.
+    Rough {
+        /// Color as 8 bit sRGB.
+        color: Color,
+        /// Width of the reflection lobe, 0 is a mirror and 255 fully diffuse.
+        roughness: u8,
+    },
+    /// Surface emitting light, which also makes it an area light.
+    Emission {
+        /// Color of the light as 8 bit sRGB.
+        color: Color,
+        /// Strength of the light, see [`emission_strength_from_u8`].
+        emission: u8,
+    },
+    /// Transparent medium like glass or water. Light passing through one unit of it
+    /// is tinted by `color` raised to the power of `density` (Beer-Lambert).
+    Glass {
+        /// Tint as 8 bit sRGB.
+        color: Color,
+        /// Index of refraction.
+        ior: f32,
+        /// Exponent of the tint per unit of distance, 0 is clear.
+        density: f32,
+    },
+}
+
+impl VoxelMaterial {
+    /// Rough material with an sRGB color.
+    pub fn rough(color: Color, roughness: u8) -> Self {
+        Self::Rough { color, roughness }
+    }
+
+    /// Emissive material with an sRGB color.
+    pub fn emissive(color: Color, emission: u8) -> Self {
+        Self::Emission { color, emission }
+    }
+
+    /// Glass with an sRGB tint, see [`VoxelMaterial::Glass`].
+    pub fn glass(color: Color, ior: f64, density: f64) -> Self {
+        Self::Glass {
+            color,
+            ior: ior as f32,
+            density: density as f32,
+        }
+    }
+
+    /// Linear color of the material.
+    pub fn color(&self) -> Vec3 {
+        match self {
+            Self::Rough { color, .. }
+            | Self::Emission { color, .. }
+            | Self::Glass { color, .. } => color_to_linear(color),
+        }
+    }
+
+    /// Identifier of the material for [`crate::aov::Aov::MaterialId`], a hash of its
+    /// parameters which stays the same between renders. It fits into 24 bits so that it
+    /// survives 32 bit float images, 0 is left for empty space.
+    pub fn id(&self) -> u32 {
+        // FNV-1a
+        let mut hash: u32 = 0x811c9dc5;
+        let mut add = |bytes: &[u8]| {
+            for &b in bytes {
+                hash = (hash ^ b as u32).wrapping_mul(0x01000193);
+            }
+        };
+        match self {
+            Self::Rough { color, roughness } => add(&[0, color[0], color[1], color[2], *roughness]),
+            Self::Emission { color, emission } => {
+                add(&[1, color[0], color[1], color[2], *emission])
+            }
+            Self::Glass {
+                color,
+                ior,
+                density,
+            } => {
+                add(&[2, color[0], color[1], color[2]]);
+                add(&ior.to_le_bytes());
+                add(&density.to_le_bytes());
+            }
+        }
+        (hash & 0xff_ffff).max(1)
+    }
+}
+
+/// Octree holding the material of every solid voxel.
+pub type MatTree = Octree<VoxelMaterial>;
+
+/// Radiance of an emission value, doubling every 16 steps.
+pub fn emission_strength_from_u8(u: u8) -> f64 {
+    (2_f64).powf(u as f64 / 16.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_decodes_to_known_values() {
+        for (c, linear) in [
+            (0, 0.),
+            (10, 0.0030352698),
+            (11, 0.0033465358),
+            (128, 0.2158605001),
+            (188, 0.5028864580),
+            (255, 1.),
+        ] {
+            assert!((srgb_to_linear(c) - linear).abs() < 1e-9, "{c}");
+        }
+        for c in 0..=255 {
+            let encoded = crate::output::linear_to_srgb(srgb_to_linear(c)) * 255.;
+            assert!((encoded - c as f64).abs() < 1e-9, "{c}");
+        }
+    }
+
+    #[test]
+    fn materials_decode_linear_colors() {
+        let material = VoxelMaterial::rough([128, 0, 255], 255);
+        assert_eq!(material.color(), Vec3::new(srgb_to_linear(128), 0., 1.));
+        // every voxel stores a material, it has to stay as small as a few bytes
+        assert!(std::mem::size_of::<VoxelMaterial>() <= 12);
+    }
+
+    #[test]
+    fn material_ids_tell_materials_apart() {
+        let materials = [
+            VoxelMaterial::rough([128, 0, 255], 255),
+            VoxelMaterial::rough([128, 0, 255], 254),
+            VoxelMaterial::rough([128, 1, 255], 255),
+            VoxelMaterial::emissive([128, 0, 255], 255),
+            VoxelMaterial::glass([128, 0, 255], 1.5, 0.),
+            VoxelMaterial::glass([128, 0, 255], 1.5, 0.5),
+        ];
+        for (i, a) in materials.iter().enumerate() {
+            assert_eq!(a.id(), a.clone().id());
+            assert!(a.id() > 0 && a.id() < 1 << 24);
+            for b in &materials[i + 1..] {
+                assert_ne!(a.id(), b.id(), "{a:?} {b:?}");
+            }
+        }
+    }
+}
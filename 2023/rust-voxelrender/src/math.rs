@@ -0,0 +1,377 @@
+//! Vector math and axis aligned cubes used by the octrees.
+
+/// A point or direction in world space, z points down.
+#[derive(Debug, Clone, Copy)]
+pub struct Vec3 {
+    /// Component along the x axis.
+    pub x: f64,
+    /// Component along the y axis.
+    pub y: f64,
+    /// Component along the z axis.
+    pub z: f64,
+}
+
+impl PartialEq for Vec3 {
+    fn eq(&self, other: &Self) -> bool {
+        self.x == other.x && self.y == other.y && self.z == other.z
+    }
+}
+
+impl Vec3 {
+    /// Vector from integer coordinates, like the corner of a voxel.
+    pub fn newi(x: i64, y: i64, z: i64) -> Vec3 {
+        Vec3::new(x as f64, y as f64, z as f64)
+    }
+
+    /// Vector from its components.
+    pub fn new(x: f64, y: f64, z: f64) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    /// Linear blend from `self` at `a = 0` to `other` at `a = 1`.
+    pub fn mixf(&self, other: &Vec3, a: f64) -> Vec3 {
+        let b = 1. - a;
+        Vec3::new(
+            self.x * b + other.x * a,
+            self.y * b + other.y * a,
+            self.z * b + other.z * a,
+        )
+    }
+
+    /// Component wise sum.
+    pub fn add(&self, v: &Vec3) -> Vec3 {
+        Vec3 {
+            x: self.x + v.x,
+            y: self.y + v.y,
+            z: self.z + v.z,
+        }
+    }
+
+    /// Component wise difference.
+    pub fn sub(&self, v: &Vec3) -> Vec3 {
+        Vec3 {
+            x: self.x - v.x,
+            y: self.y - v.y,
+            z: self.z - v.z,
+        }
+    }
+
+    /// Component wise product, which tints colors.
+    pub fn mul(&self, v: &Vec3) -> Vec3 {
+        Vec3 {
+            x: self.x * v.x,
+            y: self.y * v.y,
+            z: self.z * v.z,
+        }
+    }
+
+    /// Dot product.
+    pub fn dot(&self, v: &Vec3) -> f64 {
+        self.x * v.x + self.y * v.y + self.z * v.z
+    }
+
+    /// Raises every component to the power `f`.
+    pub fn powf(&self, f: f64) -> Vec3 {
+        Vec3::new(self.x.powf(f), self.y.powf(f), self.z.powf(f))
+    }
+
+    /// Scales every component by `s`.
+    pub fn mulf(&self, s: f64) -> Vec3 {
+        Vec3 {
+            x: self.x * s,
+            y: self.y * s,
+            z: self.z * s,
+        }
+    }
+
+    /// Unit vector pointing the same way.
+    pub fn normalized(&self) -> Vec3 {
+        self.mulf(1. / self.len())
+    }
+
+    /// Euclidean length.
+    pub fn len(&self) -> f64 {
+        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+
+    /// Rotates around the z axis by `angle` radians.
+    pub fn rotate_z(&self, angle: f64) -> Vec3 {
+        Vec3::new(
+            self.x * angle.cos() - self.y * angle.sin(),
+            self.x * angle.sin() + self.y * angle.cos(),
+            self.z,
+        )
+    }
+
+    /// Rotates around the y axis by `angle` radians.
+    pub fn rotate_y(&self, angle: f64) -> Vec3 {
+        Vec3::new(
+            self.x * angle.cos() - self.z * angle.sin(),
+            self.y,
+            -self.x * angle.sin() + self.z * angle.cos(),
+        )
+    }
+
+    /// Rotates around the x axis by `angle` radians.
+    pub fn rotate_x(&self, angle: f64) -> Vec3 {
+        Vec3::new(
+            self.x,
+            self.y * angle.cos() - self.z * angle.sin(),
+            self.y * angle.sin() + self.z * angle.cos(),
+        )
+    }
+
+    /// Cross product.
+    pub fn cross(&self, other: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    /// Rotates around the unit vector `axis` by `angle` radians (Rodrigues' formula).
+    pub fn rotate_rel(&self, angle: f64, axis: &Vec3) -> Vec3 {
+        let (sin, cos) = angle.sin_cos();
+        self.mulf(cos)
+            .add(&axis.cross(self).mulf(sin))
+            .add(&axis.mulf(axis.dot(self)).mulf(1. - cos))
+    }
+
+    /// Center of the unit voxel this position lies in.
+    pub fn voxel_center(&self) -> Vec3 {
+        Vec3::new(
+            self.x.floor() + 0.5,
+            self.y.floor() + 0.5,
+            self.z.floor() + 0.5,
+        )
+    }
+}
+
+fn max_or<T>(a: f64, b: f64, at: T, bt: T) -> (f64, T) {
+    if a >= b {
+        return (a, at);
+    }
+    (b, bt)
+}
+
+/// Interval along one axis in which a ray is between `lo` and `lo + size`.
+pub(crate) fn slab(lo: f64, size: f64, origin: f64, inv_dir: f64) -> (f64, f64) {
+    if inv_dir.is_infinite() {
+        // parallel to the walls, the ray is either always or never between them
+        if origin >= lo && origin < lo + size {
+            return (f64::NEG_INFINITY, f64::INFINITY);
+        }
+        return (f64::INFINITY, f64::NEG_INFINITY);
+    }
+    let a = (lo - origin) * inv_dir;
+    let b = (lo + size - origin) * inv_dir;
+    (a.min(b), a.max(b))
+}
+
+/// Axis aligned cube spanning from `fpos` (inclusive) to `fpos + size` (exclusive).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cube {
+    /// Corner with the smallest coordinates.
+    pub fpos: Vec3,
+    /// Edge length.
+    pub size: f64,
+}
+
+impl Cube {
+    /// Cube with its smallest corner at (x, y, z).
+    pub fn new(x: f64, y: f64, z: f64, size: f64) -> Cube {
+        Cube {
+            fpos: Vec3::new(x, y, z),
+            size,
+        }
+    }
+
+    /// Distance from `p` to the closest point of the cube, 0 inside of it.
+    pub fn distance_to(&self, p: &Vec3) -> f64 {
+        let pos = self.fpos;
+        let s = self.size;
+        let dx = (pos.x - p.x).max(p.x - (pos.x + s)).max(0.);
+        let dy = (pos.y - p.y).max(p.y - (pos.y + s)).max(0.);
+        let dz = (pos.z - p.z).max(p.z - (pos.z + s)).max(0.);
+
+        (dx * dx + dy * dy + dz * dz).sqrt()
+    }
+
+    /// Is `p` inside of the cube?
+    pub fn containsf(&self, p: &Vec3) -> bool {
+        let pos = self.fpos;
+        let size = self.size;
+
+        !(pos.x > p.x
+            || pos.x + size <= p.x
+            || pos.y > p.y
+            || pos.y + size <= p.y
+            || pos.z > p.z
+            || pos.z + size <= p.z)
+    }
+
+    /// Is any part of the cube inside the box spanning from min (inclusive) to max (exclusive)?
+    pub fn overlaps_box(&self, min: &Vec3, max: &Vec3) -> bool {
+        let pos = self.fpos;
+        let size = self.size;
+        pos.x < max.x
+            && pos.x + size > min.x
+            && pos.y < max.y
+            && pos.y + size > min.y
+            && pos.z < max.z
+            && pos.z + size > min.z
+    }
+
+    /// Is the whole cube inside the box spanning from min (inclusive) to max (exclusive)?
+    pub fn inside_box(&self, min: &Vec3, max: &Vec3) -> bool {
+        let pos = self.fpos;
+        let size = self.size;
+        pos.x >= min.x
+            && pos.x + size <= max.x
+            && pos.y >= min.y
+            && pos.y + size <= max.y
+            && pos.z >= min.z
+            && pos.z + size <= max.z
+    }
+
+    /// Bounds of the octant `idx`, using the same bit layout as `OOctree::index_of`.
+    pub fn octant(&self, idx: usize) -> Cube {
+        let hs = self.size * 0.5;
+        let mut octant = Cube::new(self.fpos.x, self.fpos.y, self.fpos.z, hs);
+        if idx & 0b100 != 0 {
+            octant.fpos.x += hs;
+        }
+        if idx & 0b010 != 0 {
+            octant.fpos.y += hs;
+        }
+        if idx & 0b001 != 0 {
+            octant.fpos.z += hs;
+        }
+        octant
+    }
+
+    /// Parametric interval [t_enter, t_exit] in which the ray `origin + t * dir` is inside
+    /// the cube, with `inv_dir` being `1 / dir` per component. The normal is the one of the
+    /// wall through which the ray enters. Returns None if the ray misses the cube entirely.
+    pub fn ray_interval(&self, origin: &Vec3, inv_dir: &Vec3) -> Option<(f64, f64, Vec3)> {
+        let (x0, x1) = slab(self.fpos.x, self.size, origin.x, inv_dir.x);
+        let (y0, y1) = slab(self.fpos.y, self.size, origin.y, inv_dir.y);
+        let (z0, z1) = slab(self.fpos.z, self.size, origin.z, inv_dir.z);
+
+        let (t_enter, normal) = (x0, Vec3::new(-inv_dir.x.signum(), 0., 0.));
+        let (t_enter, normal) = max_or(t_enter, y0, normal, Vec3::new(0., -inv_dir.y.signum(), 0.));
+        let (t_enter, normal) = max_or(t_enter, z0, normal, Vec3::new(0., 0., -inv_dir.z.signum()));
+        let t_exit = x1.min(y1).min(z1);
+        if t_enter > t_exit {
+            return None;
+        }
+        Some((t_enter, t_exit, normal))
+    }
+
+    /// Center point of the cube.
+    pub fn center(&self) -> Vec3 {
+        let s2 = self.size / 2.;
+        Vec3::new(self.fpos.x + s2, self.fpos.y + s2, self.fpos.z + s2)
+    }
+
+    /// Distance from `p` to the farthest corner of the cube.
+    pub fn max_border_dist(&self, p: &Vec3) -> f64 {
+        let s = self.size;
+        Vec3::new(
+            (p.x - self.fpos.x).abs().max((self.fpos.x + s - p.x).abs()),
+            (p.y - self.fpos.y).abs().max((self.fpos.y + s - p.y).abs()),
+            (p.z - self.fpos.z).abs().max((self.fpos.z + s - p.z).abs()),
+        )
+        .len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sampling::Rng, test_util::*};
+
+    #[test]
+    fn cross_product() {
+        let x = Vec3::new(1., 0., 0.);
+        let y = Vec3::new(0., 1., 0.);
+        assert_close(&x.cross(&y), &Vec3::new(0., 0., 1.));
+        assert_close(&y.cross(&x), &Vec3::new(0., 0., -1.));
+
+        let mut rng = Rng::new(7);
+        for _ in 0..100 {
+            let a = unit_vec(&mut rng).mulf(range(&mut rng, 0.1, 10.));
+            let b = unit_vec(&mut rng).mulf(range(&mut rng, 0.1, 10.));
+            let c = a.cross(&b);
+            assert!(c.dot(&a).abs() < 1e-9 * a.len() * c.len().max(1.));
+            assert!(c.dot(&b).abs() < 1e-9 * b.len() * c.len().max(1.));
+            assert_close(&c, &b.cross(&a).mulf(-1.));
+        }
+    }
+
+    #[test]
+    fn normalized_has_unit_length() {
+        let mut rng = Rng::new(3);
+        for _ in 0..100 {
+            let v = unit_vec(&mut rng).mulf(range(&mut rng, 1e-3, 1e3));
+            let n = v.normalized();
+            assert!((n.len() - 1.).abs() < EPS);
+            assert!((n.dot(&v) - v.len()).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn rotate_rel_rotates_around_axis() {
+        let z = Vec3::new(0., 0., 1.);
+        let x = Vec3::new(1., 0., 0.);
+        assert_close(&x.rotate_rel(PI_2, &z), &Vec3::new(0., 1., 0.));
+        assert_close(&x.rotate_rel(PI_2, &z), &x.rotate_z(PI_2));
+
+        let mut rng = Rng::new(11);
+        for _ in 0..100 {
+            let v = unit_vec(&mut rng).mulf(range(&mut rng, 0.1, 10.));
+            let axis = unit_vec(&mut rng);
+            let angle = range(&mut rng, -6.3, 6.3);
+            let r = v.rotate_rel(angle, &axis);
+            // length and the component along the axis are preserved
+            assert!((r.len() - v.len()).abs() < 1e-9);
+            assert!((r.dot(&axis) - v.dot(&axis)).abs() < 1e-9);
+            assert_close(&r.rotate_rel(-angle, &axis), &v);
+        }
+    }
+
+    #[test]
+    fn cube_contains_is_half_open() {
+        let c = Cube::new(0., 0., 0., 2.);
+        assert!(c.containsf(&Vec3::new(0., 0., 0.)));
+        assert!(c.containsf(&Vec3::new(1.999, 1., 0.5)));
+        assert!(!c.containsf(&Vec3::new(2., 1., 1.)));
+        assert!(!c.containsf(&Vec3::new(1., -0.001, 1.)));
+    }
+
+    #[test]
+    fn cube_distance_to() {
+        let c = Cube::new(0., 0., 0., 2.);
+        assert_eq!(c.distance_to(&Vec3::new(1., 1., 1.)), 0.);
+        assert_eq!(c.distance_to(&Vec3::new(2., 1., 1.)), 0.);
+        assert!((c.distance_to(&Vec3::new(5., 1., 1.)) - 3.).abs() < EPS);
+        assert!((c.distance_to(&Vec3::new(-3., -4., 1.)) - 5.).abs() < EPS);
+        assert!((c.distance_to(&Vec3::new(3., 3., 3.)) - 3_f64.sqrt()).abs() < EPS);
+    }
+
+    #[test]
+    fn cube_ray_interval() {
+        let c = Cube::new(0., 0., 0., 2.);
+        let dir = Vec3::new(1., 0., 0.);
+        let inv = Vec3::new(1. / dir.x, 1. / dir.y, 1. / dir.z);
+        let (t0, t1, n) = c.ray_interval(&Vec3::new(-3., 1., 1.), &inv).unwrap();
+        assert_eq!((t0, t1), (3., 5.));
+        assert_close(&n, &Vec3::new(-1., 0., 0.));
+        // parallel to a wall and outside of the cube
+        assert!(c.ray_interval(&Vec3::new(-3., 3., 1.), &inv).is_none());
+        // behind the origin counts as well, callers clamp to t >= 0
+        let (t0, t1, _) = c.ray_interval(&Vec3::new(1., 1., 1.), &inv).unwrap();
+        assert_eq!((t0, t1), (-1., 1.));
+    }
+}
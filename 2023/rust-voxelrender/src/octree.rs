@@ -0,0 +1,694 @@
+//! Sparse voxel octree and ray casting through it.
+
+use crate::math::{slab, Cube, Vec3};
+
+/// Sparse octree of unit voxels inside `bounds`. Uniform regions are stored as a
+/// single coarse node.
+pub struct Octree<T> {
+    data: OOctree<T>,
+    /// Region covered by the tree, voxels outside of it are ignored.
+    pub bounds: Cube,
+}
+
+pub(crate) struct OOctree<T> {
+    data: OctreeData<T>,
+}
+
+enum OctreeData<T> {
+    Empty,
+    Voxel(T),
+    Split(Box<[OOctree<T>; 8]>),
+}
+
+impl<T> Octree<T>
+where
+    T: Clone + PartialEq,
+{
+    /// Empty tree covering `bounds`.
+    pub fn new(bounds: Cube) -> Self {
+        Octree {
+            data: OOctree::new(),
+            bounds,
+        }
+    }
+
+    /// Sets the unit voxel containing `position`, positions outside of the bounds are ignored.
+    pub fn insert(&mut self, position: Vec3, voxel: T) {
+        self.data.insert(&self.bounds, position, voxel);
+    }
+
+    /// Clears the unit voxel containing `position`.
+    pub fn remove(&mut self, position: Vec3) {
+        self.data.remove(&self.bounds, position);
+    }
+
+    /// Sets every unit voxel from `min` to `max`, both inclusive. Nodes which are
+    /// completely covered become a single voxel without being split.
+    pub fn fill_box(&mut self, min: Vec3, max: Vec3, voxel: T) {
+        let end = max.add(&Vec3::new(1., 1., 1.));
+        self.data.set_box(&self.bounds, &min, &end, Some(&voxel));
+    }
+
+    /// Clears every unit voxel from `min` to `max`, both inclusive.
+    pub fn clear_box(&mut self, min: Vec3, max: Vec3) {
+        let end = max.add(&Vec3::new(1., 1., 1.));
+        self.data.set_box(&self.bounds, &min, &end, None);
+    }
+
+    /// Walks the octree front to back along the ray and returns the first voxel hit
+    /// within `max_t`, together with the number of visited nodes.
+    pub fn raycast(
+        &self,
+        origin: &Vec3,
+        dir: &Vec3,
+        max_t: f64,
+    ) -> (Option<OctreeHit<'_, T>>, usize) {
+        self.raycast_until(origin, dir, max_t, |voxel| voxel.is_some())
+    }
+
+    /// Like [`Octree::raycast`], but stops at the first node for which `stop` returns
+    /// true, with None standing for empty space. This can find where a ray leaves a
+    /// region, e.g. the first node which isn't the material the ray is travelling in.
+    pub fn raycast_until<F>(
+        &self,
+        origin: &Vec3,
+        dir: &Vec3,
+        max_t: f64,
+        stop: F,
+    ) -> (Option<OctreeHit<'_, T>>, usize)
+    where
+        F: Fn(Option<&T>) -> bool,
+    {
+        let ray = OctreeRay::new(origin, dir, max_t);
+        let b = &self.bounds;
+        let (x0, x1) = slab(b.fpos.x, b.size, origin.x, ray.inv_dir[0]);
+        let (y0, y1) = slab(b.fpos.y, b.size, origin.y, ray.inv_dir[1]);
+        let (z0, z1) = slab(b.fpos.z, b.size, origin.z, ray.inv_dir[2]);
+        let slabs = Slabs {
+            lo: [x0, y0, z0],
+            hi: [x1, y1, z1],
+        };
+        let mut steps = 0;
+        let hit = self.data.raycast(b, &ray, slabs, &stop, &mut steps);
+        (hit, steps)
+    }
+}
+
+impl<T> OOctree<T>
+where
+    T: Clone + PartialEq,
+{
+    pub(crate) fn new() -> Self {
+        OOctree {
+            data: OctreeData::Empty,
+        }
+    }
+
+    fn insert(&mut self, bounds: &Cube, position: Vec3, voxel: T) {
+        if !bounds.containsf(&position) {
+            return;
+        }
+        if bounds.size == 1. {
+            self.data = OctreeData::Voxel(voxel);
+            return;
+        }
+        let (idx, subbounds) = self.index_of(bounds, &position);
+        match &mut self.data {
+            OctreeData::Split(children) => {
+                children[idx].insert(&subbounds, position, voxel);
+                self.collapse();
+            }
+            OctreeData::Empty => {
+                self.split();
+                self.insert(bounds, position, voxel);
+            }
+            OctreeData::Voxel(v) => {
+                if *v == voxel {
+                    return;
+                }
+                // a coarse voxel is split into eight copies of itself before one of them changes
+                let v = v.clone();
+                self.split_filled(v);
+                self.insert(bounds, position, voxel);
+            }
+        }
+    }
+
+    fn remove(&mut self, bounds: &Cube, position: Vec3) {
+        if !bounds.containsf(&position) {
+            return;
+        }
+        let (idx, subbounds) = self.index_of(bounds, &position);
+        match &mut self.data {
+            OctreeData::Empty => {}
+            OctreeData::Voxel(_) if bounds.size == 1. => {
+                self.data = OctreeData::Empty;
+            }
+            OctreeData::Voxel(v) => {
+                let v = v.clone();
+                self.split_filled(v);
+                self.remove(bounds, position);
+            }
+            OctreeData::Split(children) => {
+                children[idx].remove(&subbounds, position);
+                self.collapse();
+            }
+        }
+    }
+
+    fn set_box(&mut self, bounds: &Cube, min: &Vec3, max: &Vec3, voxel: Option<&T>) {
+        if !bounds.overlaps_box(min, max) {
+            return;
+        }
+        // unit voxels which are only partially covered count as covered if their center is
+        let c = bounds.center();
+        let center_covered = c.x >= min.x
+            && c.x < max.x
+            && c.y >= min.y
+            && c.y < max.y
+            && c.z >= min.z
+            && c.z < max.z;
+        if bounds.inside_box(min, max) || (bounds.size <= 1. && center_covered) {
+            self.data = match voxel {
+                Some(v) => OctreeData::Voxel(v.clone()),
+                None => OctreeData::Empty,
+            };
+            return;
+        }
+        if bounds.size <= 1. {
+            return;
+        }
+        match (&self.data, voxel) {
+            (OctreeData::Empty, None) => return,
+            (OctreeData::Voxel(v), Some(n)) if v == n => return,
+            (OctreeData::Empty, Some(_)) => self.split(),
+            (OctreeData::Voxel(v), _) => {
+                let v = v.clone();
+                self.split_filled(v);
+            }
+            (OctreeData::Split(_), _) => {}
+        }
+        if let OctreeData::Split(children) = &mut self.data {
+            for (idx, child) in children.iter_mut().enumerate() {
+                child.set_box(&bounds.octant(idx), min, max, voxel);
+            }
+        }
+        self.collapse();
+    }
+
+    // Merges the children back into this node if all of them are empty or the same voxel.
+    fn collapse(&mut self) {
+        let OctreeData::Split(children) = &self.data else {
+            return;
+        };
+        let merged = match &children[0].data {
+            OctreeData::Empty if children.iter().all(|c| matches!(c.data, OctreeData::Empty)) => {
+                OctreeData::Empty
+            }
+            OctreeData::Voxel(v)
+                if children
+                    .iter()
+                    .all(|c| matches!(&c.data, OctreeData::Voxel(o) if o == v)) =>
+            {
+                OctreeData::Voxel(v.clone())
+            }
+            _ => return,
+        };
+        self.data = merged;
+    }
+
+    fn split(&mut self) {
+        self.data = OctreeData::Split(Box::new([
+            Self::new(),
+            Self::new(),
+            Self::new(),
+            Self::new(),
+            Self::new(),
+            Self::new(),
+            Self::new(),
+            Self::new(),
+        ]));
+    }
+
+    fn split_filled(&mut self, voxel: T) {
+        self.data = OctreeData::Split(Box::new(std::array::from_fn(|_| OOctree {
+            data: OctreeData::Voxel(voxel.clone()),
+        })));
+    }
+
+    pub(crate) fn index_of(&self, bounds: &Cube, p: &Vec3) -> (usize, Cube) {
+        let hs = bounds.size * 0.5;
+        let mut bounds = *bounds;
+        let mut idx = 0;
+        bounds.size = hs;
+        if p.x >= bounds.fpos.x + hs {
+            idx |= 0b100;
+            bounds.fpos.x += hs;
+        }
+        if p.y >= bounds.fpos.y + hs {
+            idx |= 0b010;
+            bounds.fpos.y += hs;
+        }
+        if p.z >= bounds.fpos.z + hs {
+            idx |= 0b001;
+            bounds.fpos.z += hs;
+        }
+        (idx, bounds)
+    }
+
+    // `slabs` holds the per axis intervals of the ray inside of this node, which is all
+    // that is needed to derive the intervals of the children from the center planes.
+    fn raycast<'a, F>(
+        &'a self,
+        bounds: &Cube,
+        ray: &OctreeRay,
+        slabs: Slabs,
+        stop: &F,
+        steps: &mut usize,
+    ) -> Option<OctreeHit<'a, T>>
+    where
+        F: Fn(Option<&T>) -> bool,
+    {
+        let (t0, entry_axis) = slabs.enter(ray.t_min);
+        let t1 = slabs.exit(ray.t_max);
+        if t0 >= t1 {
+            return None;
+        }
+        *steps += 1;
+        let voxel = match &self.data {
+            OctreeData::Empty => None,
+            OctreeData::Voxel(voxel) => Some(voxel),
+            OctreeData::Split(children) => {
+                let hs = bounds.size * 0.5;
+                let mid = [bounds.fpos.x + hs, bounds.fpos.y + hs, bounds.fpos.z + hs];
+                let mut t_mid = [0.; 3];
+                for axis in 0..3 {
+                    t_mid[axis] = if ray.inv_dir[axis].is_infinite() {
+                        // parallel rays stay in the half they start in
+                        if ray.origin[axis] < mid[axis] {
+                            f64::INFINITY
+                        } else {
+                            f64::NEG_INFINITY
+                        }
+                    } else {
+                        (mid[axis] - ray.origin[axis]) * ray.inv_dir[axis]
+                    };
+                }
+                // Flipping the index bits of the axes the ray travels backwards along gives
+                // an order in which a child can only be hit after all children before it.
+                for i in 0..8 {
+                    let idx = i ^ ray.octant_mask;
+                    let mut child_slabs = slabs;
+                    for (axis, bit) in [0b100, 0b010, 0b001].into_iter().enumerate() {
+                        if i & bit != 0 {
+                            child_slabs.lo[axis] = t_mid[axis];
+                        } else {
+                            child_slabs.hi[axis] = t_mid[axis];
+                        }
+                    }
+                    let child = &children[idx];
+                    if let OctreeData::Empty = child.data {
+                        if !stop(None) {
+                            continue;
+                        }
+                    }
+                    let hit = child.raycast(&bounds.octant(idx), ray, child_slabs, stop, steps);
+                    if hit.is_some() {
+                        return hit;
+                    }
+                }
+                return None;
+            }
+        };
+        if !stop(voxel) {
+            return None;
+        }
+        // rays starting inside of the node have not entered through any wall
+        let mut normal = [0.; 3];
+        if let Some(axis) = entry_axis {
+            normal[axis] = -ray.inv_dir[axis].signum();
+        }
+        Some(OctreeHit {
+            voxel,
+            bounds: *bounds,
+            t: t0,
+            normal: Vec3::new(normal[0], normal[1], normal[2]),
+        })
+    }
+}
+
+/// First voxel along a ray found by [`Octree::raycast`].
+pub struct OctreeHit<'a, T> {
+    /// None if the ray stopped in empty space
+    pub voxel: Option<&'a T>,
+    /// bounds of the (possibly coarse) node which was hit
+    pub bounds: Cube,
+    /// distance along the ray
+    pub t: f64,
+    /// normal of the wall the ray entered the node through
+    pub normal: Vec3,
+}
+
+struct OctreeRay {
+    origin: [f64; 3],
+    inv_dir: [f64; 3],
+    octant_mask: usize,
+    t_min: f64,
+    t_max: f64,
+}
+
+impl OctreeRay {
+    fn new(origin: &Vec3, dir: &Vec3, t_max: f64) -> Self {
+        let mut octant_mask = 0;
+        if dir.x < 0. {
+            octant_mask |= 0b100;
+        }
+        if dir.y < 0. {
+            octant_mask |= 0b010;
+        }
+        if dir.z < 0. {
+            octant_mask |= 0b001;
+        }
+        OctreeRay {
+            origin: [origin.x, origin.y, origin.z],
+            inv_dir: [1. / dir.x, 1. / dir.y, 1. / dir.z],
+            octant_mask,
+            t_min: 0.,
+            t_max,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Slabs {
+    lo: [f64; 3],
+    hi: [f64; 3],
+}
+
+impl Slabs {
+    // Entry distance and the axis of the wall the ray entered through, which is None
+    // if the ray only starts inside of the slabs.
+    fn enter(&self, t_min: f64) -> (f64, Option<usize>) {
+        let mut axis = None;
+        let mut t = t_min;
+        for (a, lo) in self.lo.iter().enumerate() {
+            if *lo > t {
+                t = *lo;
+                axis = Some(a);
+            }
+        }
+        (t, axis)
+    }
+
+    fn exit(&self, t_max: f64) -> f64 {
+        self.hi.iter().fold(t_max, |t, hi| t.min(*hi))
+    }
+}
+
+/// Rays are not followed further than this.
+pub const MAX_DISTANCE: f64 = 1000.;
+
+/// Why [`cast_to_hit`] stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CastStatus {
+    /// The ray left the bounds of the tree.
+    OutOfTree,
+    /// The ray travelled [`MAX_DISTANCE`] without hitting anything.
+    MaxDistance,
+    /// The ray hit a voxel, or left the region of [`cast_until`].
+    Hit,
+}
+
+/// Everything known about a ray after it was cast through the octree.
+pub struct CastHit<'a, T> {
+    /// Voxel which was hit, None on a miss.
+    pub voxel: Option<&'a T>,
+    /// node which was hit, can be larger than a single voxel
+    pub bounds: Cube,
+    /// point where the ray entered the voxel, or where it stopped on a miss
+    pub position: Vec3,
+    /// normal of the wall the ray entered through, zero on a miss or if the ray
+    /// started inside of the voxel
+    pub normal: Vec3,
+    /// Distance travelled along the ray.
+    pub distance: f64,
+    /// number of visited octree nodes
+    pub steps: usize,
+    /// Why the ray stopped.
+    pub status: CastStatus,
+}
+
+/// Casts a ray from `pos` along `dir` until it hits a voxel, leaves the tree or
+/// travelled [`MAX_DISTANCE`].
+pub fn cast_to_hit<'a, T>(pos: Vec3, dir: &Vec3, tree: &'a Octree<T>) -> CastHit<'a, T>
+where
+    T: Clone + PartialEq,
+{
+    cast_until(pos, dir, tree, |voxel| voxel.is_some())
+}
+
+/// Casts a ray like [`cast_to_hit`], but through every node for which `stop` returns
+/// false, see [`Octree::raycast_until`]. A hit in empty space has no voxel.
+pub fn cast_until<'a, T, F>(pos: Vec3, dir: &Vec3, tree: &'a Octree<T>, stop: F) -> CastHit<'a, T>
+where
+    T: Clone + PartialEq,
+    F: Fn(Option<&T>) -> bool,
+{
+    let (hit, steps) = tree.raycast_until(&pos, dir, MAX_DISTANCE, stop);
+    if let Some(hit) = hit {
+        return CastHit {
+            voxel: hit.voxel,
+            bounds: hit.bounds,
+            position: pos.add(&dir.mulf(hit.t)),
+            normal: hit.normal,
+            distance: hit.t,
+            steps,
+            status: CastStatus::Hit,
+        };
+    }
+    let inv_dir = Vec3::new(1. / dir.x, 1. / dir.y, 1. / dir.z);
+    let t_exit = match tree.bounds.ray_interval(&pos, &inv_dir) {
+        Some((_, t_exit, _)) => t_exit.max(0.),
+        None => 0.,
+    };
+    let (distance, status) = if t_exit > MAX_DISTANCE {
+        (MAX_DISTANCE, CastStatus::MaxDistance)
+    } else {
+        (t_exit, CastStatus::OutOfTree)
+    };
+    CastHit {
+        voxel: None,
+        bounds: tree.bounds,
+        position: pos.add(&dir.mulf(distance)),
+        normal: Vec3::new(0., 0., 0.),
+        distance,
+        steps,
+        status,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sampling::Rng, test_util::*};
+
+    fn node_count<T>(tree: &OOctree<T>) -> usize {
+        match &tree.data {
+            OctreeData::Split(c) => 1 + c.iter().map(node_count).sum::<usize>(),
+            _ => 1,
+        }
+    }
+
+    #[test]
+    fn insert_remove_collapses_nodes() {
+        let mut tree = Octree::new(Cube::new(0., 0., 0., 16.));
+        for x in 0..8 {
+            for y in 0..8 {
+                for z in 0..8 {
+                    tree.insert(Vec3::newi(x, y, z), 1u8);
+                }
+            }
+        }
+        // the filled octant is a single coarse voxel
+        assert_eq!(node_count(&tree.data), 9);
+
+        tree.remove(Vec3::newi(3, 3, 3));
+        assert!(node_count(&tree.data) > 9);
+        tree.insert(Vec3::newi(3, 3, 3), 1);
+        assert_eq!(node_count(&tree.data), 9);
+
+        for x in 0..8 {
+            for y in 0..8 {
+                for z in 0..8 {
+                    tree.remove(Vec3::newi(x, y, z));
+                }
+            }
+        }
+        assert_eq!(node_count(&tree.data), 1);
+        assert!(matches!(tree.data.data, OctreeData::Empty));
+    }
+
+    #[test]
+    fn fill_box_matches_single_inserts() {
+        let mut rng = Rng::new(5);
+        for _ in 0..20 {
+            let min = [
+                int(&mut rng, 0, 15),
+                int(&mut rng, 0, 15),
+                int(&mut rng, 0, 15),
+            ];
+            let max = [
+                int(&mut rng, min[0], 16),
+                int(&mut rng, min[1], 16),
+                int(&mut rng, min[2], 16),
+            ];
+            let mut filled = Octree::new(Cube::new(0., 0., 0., 16.));
+            let mut inserted = Octree::new(Cube::new(0., 0., 0., 16.));
+            filled.fill_box(
+                Vec3::newi(min[0], min[1], min[2]),
+                Vec3::newi(max[0], max[1], max[2]),
+                2u8,
+            );
+            for x in min[0]..=max[0] {
+                for y in min[1]..=max[1] {
+                    for z in min[2]..=max[2] {
+                        inserted.insert(Vec3::newi(x, y, z), 2u8);
+                    }
+                }
+            }
+            assert_eq!(node_count(&filled.data), node_count(&inserted.data));
+
+            filled.clear_box(
+                Vec3::newi(min[0], min[1], min[2]),
+                Vec3::newi(max[0], max[1], max[2]),
+            );
+            assert_eq!(node_count(&filled.data), 1);
+        }
+    }
+
+    // Closest unit voxel along the ray by testing every voxel.
+    fn brute_force_cast(voxels: &[Vec3], origin: &Vec3, dir: &Vec3) -> Option<(f64, Vec3, Vec3)> {
+        let inv = Vec3::new(1. / dir.x, 1. / dir.y, 1. / dir.z);
+        voxels
+            .iter()
+            .filter_map(|v| {
+                let (t0, t1, n) = Cube::new(v.x, v.y, v.z, 1.).ray_interval(origin, &inv)?;
+                (t0 >= 0. && t0 < t1).then_some((t0, *v, n))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+
+    #[test]
+    fn cast_to_hit_matches_brute_force() {
+        let mut rng = Rng::new(42);
+        let bounds = Cube::new(-16., -16., -16., 32.);
+        for _ in 0..10 {
+            let mut tree = Octree::new(bounds);
+            let mut voxels = vec![];
+            for _ in 0..600 {
+                let v = Vec3::newi(
+                    int(&mut rng, -16, 16),
+                    int(&mut rng, -16, 16),
+                    int(&mut rng, -16, 16),
+                );
+                tree.insert(v, 1u8);
+                voxels.push(v);
+            }
+            // a solid block which collapses into coarse nodes
+            tree.fill_box(Vec3::newi(0, 0, 0), Vec3::newi(7, 7, 7), 1);
+            for x in 0..8 {
+                for y in 0..8 {
+                    for z in 0..8 {
+                        voxels.push(Vec3::newi(x, y, z));
+                    }
+                }
+            }
+
+            for _ in 0..300 {
+                // start in empty space, partly from outside of the tree
+                let origin = Vec3::new(
+                    range(&mut rng, -24., 24.),
+                    range(&mut rng, -24., 24.),
+                    range(&mut rng, -24., 24.),
+                );
+                if voxels
+                    .iter()
+                    .any(|v| Cube::new(v.x, v.y, v.z, 1.).containsf(&origin))
+                {
+                    continue;
+                }
+                let dir = unit_vec(&mut rng);
+                let hit = cast_to_hit(origin, &dir, &tree);
+                match brute_force_cast(&voxels, &origin, &dir) {
+                    Some((t, voxel, normal)) => {
+                        assert_eq!(hit.status, CastStatus::Hit);
+                        assert!((hit.distance - t).abs() < 1e-9, "{} != {t}", hit.distance);
+                        assert!(hit.bounds.containsf(&voxel.voxel_center()));
+                        assert_close(&hit.normal, &normal);
+                        assert_close(&hit.position, &origin.add(&dir.mulf(t)));
+                    }
+                    None => {
+                        assert!(hit.voxel.is_none());
+                        assert_eq!(hit.status, CastStatus::OutOfTree);
+                        assert!(!bounds.containsf(&hit.position.add(&dir.mulf(1e-6))));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cast_along_axis() {
+        let mut tree = Octree::new(Cube::new(-8., -8., -8., 16.));
+        tree.insert(Vec3::newi(3, 0, 0), 1u8);
+        // exactly axis aligned rays used to need a camera shake
+        let hit = cast_to_hit(Vec3::new(0.5, 0.5, 0.5), &Vec3::new(1., 0., 0.), &tree);
+        assert_eq!(hit.status, CastStatus::Hit);
+        assert!((hit.distance - 2.5).abs() < EPS);
+        assert_close(&hit.normal, &Vec3::new(-1., 0., 0.));
+
+        let hit = cast_to_hit(Vec3::new(0., 0., 0.), &Vec3::new(1., 0., 0.), &tree);
+        assert!((hit.distance - 3.).abs() < EPS);
+        let miss = cast_to_hit(Vec3::new(0.5, 1., 0.5), &Vec3::new(1., 0., 0.), &tree);
+        assert_eq!(miss.status, CastStatus::OutOfTree);
+    }
+
+    #[test]
+    fn cast_until_leaves_region() {
+        let mut tree = Octree::new(Cube::new(-16., -16., -16., 32.));
+        tree.fill_box(Vec3::newi(0, 0, 0), Vec3::newi(3, 3, 3), 1u8);
+        tree.insert(Vec3::newi(4, 1, 1), 2);
+        let inside = |v: Option<&u8>| v != Some(&1);
+        // leaves into empty space
+        let hit = cast_until(
+            Vec3::new(1.5, 2.5, 1.5),
+            &Vec3::new(0., 1., 0.),
+            &tree,
+            inside,
+        );
+        assert_eq!(hit.status, CastStatus::Hit);
+        assert_eq!(hit.voxel, None);
+        assert!((hit.distance - 1.5).abs() < EPS);
+        assert_close(&hit.normal, &Vec3::new(0., -1., 0.));
+        // leaves straight into another voxel
+        let hit = cast_until(
+            Vec3::new(0.5, 1.5, 1.5),
+            &Vec3::new(1., 0., 0.),
+            &tree,
+            inside,
+        );
+        assert_eq!(hit.voxel, Some(&2));
+        assert!((hit.distance - 3.5).abs() < EPS);
+        // leaves the tree
+        let mut tree = Octree::new(Cube::new(0., 0., 0., 4.));
+        tree.fill_box(Vec3::newi(0, 0, 0), Vec3::newi(3, 3, 3), 1u8);
+        let hit = cast_until(
+            Vec3::new(1.5, 2.5, 1.5),
+            &Vec3::new(0., 0., 1.),
+            &tree,
+            inside,
+        );
+        assert_eq!(hit.status, CastStatus::OutOfTree);
+        assert!((hit.distance - 2.5).abs() < EPS);
+    }
+}
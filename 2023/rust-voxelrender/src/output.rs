@@ -0,0 +1,389 @@
+//! Tone mapping and conversion of rendered colors into images.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use image::{
+    codecs::hdr::{HdrDecoder, HdrEncoder},
+    ImageBuffer, ImageFormat, ImageResult, Rgb, Rgb32FImage, RgbImage,
+};
+
+use crate::{material::Color, math::Vec3};
+
+/// ACES filmic curve fitted by Krzysztof Narkowicz, maps linear radiance to linear [0, 1].
+pub fn aces(x: f64) -> f64 {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    ((x * (a * x + b)) / (x * (c * x + d) + e)).clamp(0.0, 1.0)
+}
+
+/// Filmic curve by Jim Hejl and Richard Burgess-Dawson. Its result already contains a
+/// gamma of about 2.2.
+pub fn filmic_tone_mapping(color: f64) -> f64 {
+    let color = (0_f64).max(color - 0.004);
+    (color * (6.2 * color + 0.5)) / (color * (6.2 * color + 1.7) + 0.06)
+}
+
+/// Extended Reinhard operator on the luma of `color`, which maps a luma of `white` to 1
+/// and keeps the hue. The result is linear.
+pub fn white_preserving_luma_based_reinhard_tone_mapping(color: &Vec3, white: f64) -> Vec3 {
+    let luma = color.dot(&Vec3::new(0.2126, 0.7152, 0.0722));
+    if luma <= 0. {
+        return Vec3::new(0., 0., 0.);
+    }
+    let tone_mapped_luma = luma * (1. + luma / (white * white)) / (1. + luma);
+    color.mulf(tone_mapped_luma / luma)
+}
+
+/// sRGB transfer function, encodes a linear value in [0, 1] for display.
+pub fn linear_to_srgb(x: f64) -> f64 {
+    let x = x.clamp(0., 1.);
+    if x <= 0.0031308 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Curve compressing linear radiance into the displayable range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneOperator {
+    /// Filmic curve by Jim Hejl and Richard Burgess-Dawson, see [`filmic_tone_mapping`].
+    Filmic,
+    /// ACES curve fitted by Krzysztof Narkowicz, see [`aces`].
+    Aces,
+    /// Reinhard on the luma which keeps the hue, see
+    /// [`white_preserving_luma_based_reinhard_tone_mapping`].
+    Reinhard,
+    /// Divides by the white point, anything above it is out of range.
+    Linear,
+    /// Clips every channel to [0, 1].
+    Clamp,
+}
+
+// white point of the reinhard operator if none is set
+const REINHARD_WHITE: f64 = 2.;
+
+impl ToneOperator {
+    /// Maps linear radiance to linear display values. With a `white` point the input
+    /// `white` maps to 1, otherwise every operator uses its own curve.
+    pub fn apply(&self, c: &Vec3, white: Option<f64>) -> Vec3 {
+        // filmic and aces approach 1 slowly, a white point rescales them
+        let scaled = |f: fn(f64) -> f64| {
+            let max = white.map_or(1., f);
+            Vec3::new(f(c.x), f(c.y), f(c.z)).mulf(1. / max)
+        };
+        match self {
+            // undo the gamma which is part of the curve
+            ToneOperator::Filmic => scaled(filmic_tone_mapping).powf(2.2),
+            ToneOperator::Aces => scaled(aces),
+            ToneOperator::Reinhard => {
+                let white = white.unwrap_or(REINHARD_WHITE);
+                white_preserving_luma_based_reinhard_tone_mapping(c, white)
+            }
+            ToneOperator::Linear => c.mulf(1. / white.unwrap_or(1.)),
+            ToneOperator::Clamp => {
+                Vec3::new(c.x.clamp(0., 1.), c.y.clamp(0., 1.), c.z.clamp(0., 1.))
+            }
+        }
+    }
+}
+
+impl FromStr for ToneOperator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "filmic" => Ok(ToneOperator::Filmic),
+            "aces" => Ok(ToneOperator::Aces),
+            "reinhard" => Ok(ToneOperator::Reinhard),
+            "linear" => Ok(ToneOperator::Linear),
+            "clamp" => Ok(ToneOperator::Clamp),
+            _ => Err(format!(
+                "unknown tone mapper `{s}`, expected filmic, aces, reinhard, linear or clamp"
+            )),
+        }
+    }
+}
+
+/// Maps linear radiance into displayable [0, 1] colors: the radiance is scaled by the
+/// exposure, compressed by the operator and encoded with the sRGB transfer function, or
+/// a plain gamma if one is set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneMapper {
+    /// Curve compressing the exposed radiance.
+    pub operator: ToneOperator,
+    /// Exposure in stops, every stop doubles the brightness.
+    pub exposure: f64,
+    /// Radiance which becomes full white, see [`ToneOperator::apply`].
+    pub white: Option<f64>,
+    /// Display gamma replacing the sRGB transfer function.
+    pub gamma: Option<f64>,
+}
+
+impl ToneMapper {
+    /// Tone mapper using `operator` without exposure and with sRGB encoding.
+    pub fn new(operator: ToneOperator) -> Self {
+        ToneMapper {
+            operator,
+            exposure: 0.,
+            white: None,
+            gamma: None,
+        }
+    }
+
+    /// Maps linear radiance to an encoded display color in [0, 1].
+    pub fn map(&self, c: &Vec3) -> Vec3 {
+        let exposed = c.mulf((2_f64).powf(self.exposure));
+        let l = self.operator.apply(&exposed, self.white);
+        let encode = |x: f64| match self.gamma {
+            Some(gamma) => x.clamp(0., 1.).powf(1. / gamma),
+            None => linear_to_srgb(x),
+        };
+        Vec3::new(encode(l.x), encode(l.y), encode(l.z))
+    }
+}
+
+impl Default for ToneMapper {
+    fn default() -> Self {
+        ToneMapper::new(ToneOperator::Filmic)
+    }
+}
+
+/// Tone maps a linear color and quantizes it to 8 bits.
+pub fn f_to_color(f: &Vec3, tone_mapper: &ToneMapper) -> Color {
+    let c = tone_mapper.map(f);
+    let quantize = |x: f64| (x * 255.).round() as u8;
+    [quantize(c.x), quantize(c.y), quantize(c.z)]
+}
+
+/// Tone maps row major linear pixels into an 8 bit image which can be saved in any
+/// format `image` supports.
+pub fn to_image(data: &[Vec3], w: u32, h: u32, tone_mapper: &ToneMapper) -> RgbImage {
+    let mut buffer = ImageBuffer::new(w, h);
+    for y in 0..h {
+        for x in 0..w {
+            let color = f_to_color(&data[(w * y + x) as usize], tone_mapper);
+            buffer.put_pixel(x, y, Rgb(color));
+        }
+    }
+    buffer
+}
+
+/// Copies row major linear pixels into a floating point image without tone mapping.
+pub fn to_hdr_image(data: &[Vec3], w: u32, h: u32) -> Rgb32FImage {
+    let mut buffer = ImageBuffer::new(w, h);
+    for y in 0..h {
+        for x in 0..w {
+            let c = data[(w * y + x) as usize];
+            buffer.put_pixel(x, y, Rgb([c.x as f32, c.y as f32, c.z as f32]));
+        }
+    }
+    buffer
+}
+
+/// Whether `path` has the extension of a format which keeps the linear radiance:
+/// Radiance `.hdr`, OpenEXR `.exr` or `.pfm`.
+pub fn is_hdr(path: &Path) -> bool {
+    let extension = path.extension().and_then(|e| e.to_str());
+    matches!(
+        extension.map(|e| e.to_ascii_lowercase()).as_deref(),
+        Some("hdr" | "exr" | "pfm")
+    )
+}
+
+/// Whether an image can be saved to `path`.
+pub fn is_supported(path: &Path) -> bool {
+    is_hdr(path) || ImageFormat::from_path(path).is_ok()
+}
+
+/// Saves row major linear pixels to `path` in the format of its extension. HDR formats
+/// get the linear radiance, every other format the tone mapped 8 bit colors.
+pub fn save(
+    data: &[Vec3],
+    w: u32,
+    h: u32,
+    tone_mapper: &ToneMapper,
+    path: &Path,
+) -> ImageResult<()> {
+    if !is_hdr(path) {
+        return to_image(data, w, h, tone_mapper).save(path);
+    }
+    let image = to_hdr_image(data, w, h);
+    match ImageFormat::from_path(path) {
+        Ok(ImageFormat::Hdr) => {
+            let pixels: Vec<Rgb<f32>> = image.pixels().copied().collect();
+            let file = BufWriter::new(File::create(path)?);
+            HdrEncoder::new(file).encode(&pixels, w as usize, h as usize)
+        }
+        Ok(ImageFormat::OpenExr) => image.save(path),
+        _ => {
+            let mut file = BufWriter::new(File::create(path)?);
+            write_pfm(&image, &mut file)?;
+            Ok(file.flush()?)
+        }
+    }
+}
+
+/// Opens an image as linear floats. Unlike `image::open`, which maps Radiance `.hdr`
+/// files to 8 bits, this keeps their full range.
+pub fn open_linear(path: &Path) -> ImageResult<Rgb32FImage> {
+    if ImageFormat::from_path(path).ok() != Some(ImageFormat::Hdr) {
+        return Ok(image::open(path)?.into_rgb32f());
+    }
+    let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+    let (w, h) = (decoder.metadata().width, decoder.metadata().height);
+    let pixels = decoder.read_image_hdr()?;
+    let data = pixels.iter().flat_map(|p| p.0).collect();
+    Ok(ImageBuffer::from_raw(w, h, data).expect("decoder returned every pixel"))
+}
+
+/// Writes a color Portable Float Map: a text header followed by little endian floats,
+/// with the bottom row first.
+pub fn write_pfm<W: Write>(image: &Rgb32FImage, out: &mut W) -> io::Result<()> {
+    // a negative scale marks little endian data
+    write!(out, "PF\n{} {}\n-1.0\n", image.width(), image.height())?;
+    for row in image.rows().rev() {
+        for pixel in row {
+            for c in pixel.0 {
+                out.write_all(&c.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixels() -> Vec<Vec3> {
+        vec![
+            Vec3::new(0., 0.5, 1.),
+            Vec3::new(2., 4., 8.),
+            Vec3::new(16., 0.25, 0.125),
+            Vec3::new(100., 0., 3.),
+        ]
+    }
+
+    #[test]
+    fn aces_saturates_at_white() {
+        // the clamp used to apply to the denominator, pushing bright values above 1
+        let mut last = 0.;
+        for i in 1..200 {
+            let y = aces(i as f64 / 10.);
+            assert!((y > last || y == 1.) && y <= 1., "{i}: {y}");
+            last = y;
+        }
+        assert_eq!(aces(1000.), 1.);
+        assert_eq!(aces(0.), 0.);
+    }
+
+    #[test]
+    fn srgb_encoding_matches_reference() {
+        assert_eq!(linear_to_srgb(0.), 0.);
+        assert!((linear_to_srgb(0.0031308) - 0.04045).abs() < 1e-6);
+        assert!((linear_to_srgb(0.2158605) - 128. / 255.).abs() < 1e-6);
+        assert!((linear_to_srgb(0.5) - 0.735357).abs() < 1e-6);
+        assert!((linear_to_srgb(2.) - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn exposure_doubles_per_stop() {
+        let mut tone_mapper = ToneMapper::new(ToneOperator::Linear);
+        let c = Vec3::new(0.05, 0.1, 0.2);
+        tone_mapper.exposure = 2.;
+        let brighter = tone_mapper.map(&c);
+        tone_mapper.exposure = 0.;
+        assert_eq!(brighter, tone_mapper.map(&c.mulf(4.)));
+        tone_mapper.white = Some(4.);
+        assert_eq!(f_to_color(&Vec3::new(4., 2., 0.), &tone_mapper)[0], 255);
+        tone_mapper.gamma = Some(1.);
+        assert_eq!(
+            f_to_color(&Vec3::new(4., 2., 0.), &tone_mapper),
+            [255, 128, 0]
+        );
+    }
+
+    #[test]
+    fn operators_map_into_display_range() {
+        let colors = [
+            Vec3::new(0., 0., 0.),
+            Vec3::new(0.01, 0.2, 0.5),
+            Vec3::new(1., 1., 1.),
+            Vec3::new(30., 5., 0.1),
+        ];
+        for operator in ["filmic", "aces", "reinhard", "linear", "clamp"] {
+            let operator: ToneOperator = operator.parse().unwrap();
+            for white in [None, Some(4.)] {
+                let tone_mapper = ToneMapper {
+                    white,
+                    ..ToneMapper::new(operator)
+                };
+                assert_eq!(tone_mapper.map(&colors[0]), colors[0]);
+                for c in &colors[1..] {
+                    let m = tone_mapper.map(c);
+                    for x in [m.x, m.y, m.z] {
+                        assert!((0. ..=1.).contains(&x), "{operator:?}: {x}");
+                    }
+                    // brighter channels stay brighter
+                    assert_eq!(c.x >= c.y, m.x >= m.y, "{operator:?}: {m:?}");
+                    assert_eq!(c.y >= c.z, m.y >= m.z, "{operator:?}: {m:?}");
+                }
+            }
+            let white = ToneMapper {
+                white: Some(4.),
+                ..ToneMapper::new(operator)
+            };
+            assert!(white.map(&Vec3::new(4., 4., 4.)).x > 0.999, "{operator:?}");
+        }
+    }
+
+    #[test]
+    fn pfm_stores_bottom_row_first() {
+        let mut out = vec![];
+        write_pfm(&to_hdr_image(&pixels(), 2, 2), &mut out).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&out[..header.len()], header);
+        let floats: Vec<f32> = out[header.len()..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(
+            floats,
+            [16., 0.25, 0.125, 100., 0., 3., 0., 0.5, 1., 2., 4., 8.]
+        );
+    }
+
+    #[test]
+    fn hdr_formats_keep_radiance() {
+        let dir = std::env::temp_dir().join(format!("rustree-output-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["out.hdr", "out.exr"] {
+            let path = dir.join(name);
+            assert!(is_hdr(&path));
+            save(&pixels(), 2, 2, &ToneMapper::default(), &path).unwrap();
+            let loaded = open_linear(&path).unwrap();
+            for (a, b) in loaded.pixels().zip(pixels()) {
+                // radiance files share one exponent between the channels
+                for (a, b) in a.0.iter().zip([b.x, b.y, b.z]) {
+                    assert!(
+                        (*a as f64 - b).abs() <= b.max(1.) / 64.,
+                        "{name}: {a} != {b}"
+                    );
+                }
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!is_hdr(Path::new("out.png")));
+        assert!(is_supported(Path::new("out.PFM")));
+        assert!(!is_supported(Path::new("out.txt")));
+    }
+}
@@ -0,0 +1,1689 @@
+//! Shading and the multi threaded image renderer.
+
+use std::{
+    f64::consts::FRAC_1_PI,
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use crate::{
+    aov::Aov,
+    camera::Camera,
+    lighting::{Light, LightLimits},
+    material::{color_to_linear, emission_strength_from_u8, VoxelMaterial},
+    math::Vec3,
+    octree::{cast_to_hit, cast_until, CastHit, CastStatus},
+    output::{f_to_color, ToneMapper},
+    sampling::{
+        cosine_hemisphere, ggx_alpha, ggx_g1, ggx_half_vector, stratified, PixelFilter, Rng,
+    },
+    scene::Scene,
+};
+
+const SOLID_POS_PUSH: f64 = 2e-4;
+// bounces before paths may be terminated by russian roulette
+const RUSSIAN_ROULETTE_DEPTH: usize = 3;
+// GGX widths below this are reflected like a perfect mirror
+const MIRROR_ALPHA: f64 = 1e-3;
+// separate pieces of glass a shadow ray passes through before giving up
+const MAX_SHADOW_LAYERS: usize = 16;
+
+/// Color seen along a ray, following glossy reflections for up to `settings.bounces`
+/// surfaces. The reflection lobe of a rough material is a GGX distribution which gets
+/// wider with its roughness. Rays leaving the octree see the environment, which also
+/// lights rough surfaces through `settings.sky_samples` cosine weighted rays.
+pub fn direct_color(
+    origin: &Vec3,
+    dir: &Vec3,
+    scene: &Scene,
+    settings: &RenderSettings,
+    rng: &mut Rng,
+) -> Vec3 {
+    direct_color_r(origin, dir, scene, settings, settings.bounces, rng)
+}
+
+fn direct_color_r(
+    origin: &Vec3,
+    dir: &Vec3,
+    scene: &Scene,
+    settings: &RenderSettings,
+    bounces: usize,
+    rng: &mut Rng,
+) -> Vec3 {
+    let px_color = Vec3::new(0., 0., 0.);
+    if bounces == 0 {
+        return px_color;
+    }
+    let hit = cast_to_hit(*origin, dir, &scene.tree);
+    shade_direct(&hit, dir, scene, settings, bounces, rng)
+}
+
+// Color of the voxel a ray travelling along `dir` hit, for the direct integrator.
+fn shade_direct(
+    hit: &CastHit<VoxelMaterial>,
+    dir: &Vec3,
+    scene: &Scene,
+    settings: &RenderSettings,
+    bounces: usize,
+    rng: &mut Rng,
+) -> Vec3 {
+    let px_color = Vec3::new(0., 0., 0.);
+    let Some(&voxel) = hit.voxel else {
+        return scene.environment.radiance(dir);
+    };
+    if bounces == 0 {
+        return px_color;
+    }
+    let normal = hit.normal;
+
+    if let Some(medium) = Medium::new(&voxel) {
+        if normal.len() == 0. {
+            // the ray starts inside of the glass
+            return inside_direct(&hit.position, dir, &medium, scene, settings, bounces, rng);
+        }
+        let (fresnel, refracted) = fresnel_refract(dir, &normal, 1., medium.ior);
+        let reflected = direct_color_r(
+            &hit.position.add(&normal.mulf(SOLID_POS_PUSH)),
+            &reflect(dir, &normal),
+            scene,
+            settings,
+            bounces - 1,
+            rng,
+        );
+        let Some(refracted) = refracted else {
+            return reflected;
+        };
+        let transmitted = inside_direct(
+            &hit.position.sub(&normal.mulf(SOLID_POS_PUSH)),
+            &refracted,
+            &medium,
+            scene,
+            settings,
+            bounces - 1,
+            rng,
+        );
+        return transmitted.mixf(&reflected, fresnel);
+    }
+
+    // Render light first because it is faster
+    if let VoxelMaterial::Emission { color, emission } = voxel {
+        let light_strength = emission_strength_from_u8(emission);
+        return color_to_linear(&color).mulf(light_strength);
+    }
+
+    if let VoxelMaterial::Rough {
+        color: albedo,
+        roughness,
+    } = voxel
+    {
+        let direct_light_pos = push_off_surface(&hit.position, &normal, dir);
+        let color = direct_light(&direct_light_pos, &normal, scene, settings, rng)
+            .add(&sky_light(&direct_light_pos, &normal, scene, settings, rng))
+            .mul(&color_to_linear(&albedo));
+        if roughness < 255 {
+            // only reflections seen by the camera get more than one ray, deeper ones
+            // would multiply the rays with every bounce
+            let samples = if bounces == settings.bounces {
+                settings.gloss_samples
+            } else {
+                1
+            };
+            let mut additional_color = Vec3::new(0., 0., 0.);
+            for _ in 0..samples {
+                let Some((reflection, weight)) = glossy_reflection(dir, &normal, roughness, rng)
+                else {
+                    continue;
+                };
+                let reflected = direct_color_r(
+                    &direct_light_pos,
+                    &reflection,
+                    scene,
+                    settings,
+                    bounces - 1,
+                    rng,
+                );
+                additional_color = additional_color.add(&reflected.mulf(weight / samples as f64));
+            }
+            return color.mixf(&additional_color, reflected_back(roughness));
+        }
+        return color;
+    }
+    px_color
+}
+
+// Color seen along a ray travelling through a glass medium, which is absorbed on its
+// way to where the ray leaves the medium and is split into a reflection back into the
+// medium and a refraction out of it there.
+fn inside_direct(
+    origin: &Vec3,
+    dir: &Vec3,
+    medium: &Medium,
+    scene: &Scene,
+    settings: &RenderSettings,
+    bounces: usize,
+    rng: &mut Rng,
+) -> Vec3 {
+    if bounces == 0 {
+        return Vec3::new(0., 0., 0.);
+    }
+    let exit = medium.exit(origin, dir, scene);
+    let transmittance = medium.transmittance(exit.distance);
+    if exit.status != CastStatus::Hit {
+        // the glass reaches the edge of the octree
+        return scene.environment.radiance(dir).mul(&transmittance);
+    }
+    // the normal of the wall the ray left through, pointing back into the medium
+    let normal = exit.normal;
+    let (fresnel, refracted) = fresnel_refract(dir, &normal, medium.ior, 1.);
+    let reflected = inside_direct(
+        &exit.position.add(&normal.mulf(SOLID_POS_PUSH)),
+        &reflect(dir, &normal),
+        medium,
+        scene,
+        settings,
+        bounces - 1,
+        rng,
+    );
+    let transmitted = match refracted {
+        // the ray leaves into empty space or straight into the next voxel
+        Some(refracted) if exit.voxel.is_none() => direct_color_r(
+            &exit.position.sub(&normal.mulf(SOLID_POS_PUSH)),
+            &refracted,
+            scene,
+            settings,
+            bounces - 1,
+            rng,
+        ),
+        Some(refracted) => shade_direct(&exit, &refracted, scene, settings, bounces - 1, rng),
+        None => Vec3::new(0., 0., 0.),
+    };
+    transmitted.mixf(&reflected, fresnel).mul(&transmittance)
+}
+
+// Casts a shadow ray which passes through glass without being refracted. Returns the
+// first opaque hit and the fraction of light the glass on the way lets through.
+fn cast_shadow<'a>(
+    position: &Vec3,
+    dir: &Vec3,
+    scene: &'a Scene,
+) -> (CastHit<'a, VoxelMaterial>, Vec3) {
+    let mut transmittance = Vec3::new(1., 1., 1.);
+    let mut hit = cast_to_hit(*position, dir, &scene.tree);
+    for _ in 0..MAX_SHADOW_LAYERS {
+        let Some(medium) = hit.voxel.and_then(Medium::new) else {
+            break;
+        };
+        let exit = medium.exit(
+            &hit.position.sub(&hit.normal.mulf(SOLID_POS_PUSH)),
+            dir,
+            scene,
+        );
+        transmittance = transmittance.mul(&medium.transmittance(exit.distance));
+        if exit.voxel.is_some() || exit.status != CastStatus::Hit {
+            hit = exit;
+            continue;
+        }
+        hit = cast_to_hit(
+            exit.position.sub(&exit.normal.mulf(SOLID_POS_PUSH)),
+            dir,
+            &scene.tree,
+        );
+    }
+    (hit, transmittance)
+}
+
+/// Transparent medium a ray can travel through, built from a glass material.
+pub struct Medium {
+    material: VoxelMaterial,
+    /// Index of refraction of the medium.
+    pub ior: f64,
+    // fraction of the light which passes through one unit of the medium
+    absorption: Vec3,
+}
+
+impl Medium {
+    /// The medium of a glass material, None for any other material.
+    pub fn new(material: &VoxelMaterial) -> Option<Self> {
+        let VoxelMaterial::Glass {
+            color,
+            ior,
+            density,
+        } = *material
+        else {
+            return None;
+        };
+        Some(Medium {
+            material: *material,
+            ior: ior as f64,
+            absorption: color_to_linear(&color).powf(density as f64),
+        })
+    }
+
+    /// Where a ray starting inside of the medium leaves it, the hit is in the first
+    /// node which is not made of the medium. Its normal points back into the medium.
+    pub fn exit<'a>(
+        &self,
+        origin: &Vec3,
+        dir: &Vec3,
+        scene: &'a Scene,
+    ) -> CastHit<'a, VoxelMaterial> {
+        cast_until(*origin, dir, &scene.tree, |voxel| {
+            voxel != Some(&self.material)
+        })
+    }
+
+    /// Fraction of the light which is left after travelling `distance` through the medium.
+    pub fn transmittance(&self, distance: f64) -> Vec3 {
+        self.absorption.powf(distance)
+    }
+}
+
+// Mirrors `dir` on the plane with the normal `normal`.
+fn reflect(dir: &Vec3, normal: &Vec3) -> Vec3 {
+    // r = d - 2(d \dot n)n
+    dir.sub(&normal.mulf(dir.dot(normal) * 2.))
+}
+
+// Fresnel reflectance of unpolarized light going from a medium with the refractive
+// index `n1` into one with `n2`, and the refracted direction. `normal` faces against
+// `dir`. Under total internal reflection everything is reflected and there is no
+// refracted direction.
+fn fresnel_refract(dir: &Vec3, normal: &Vec3, n1: f64, n2: f64) -> (f64, Option<Vec3>) {
+    let eta = n1 / n2;
+    let cos_i = (-dir.dot(normal)).clamp(0., 1.);
+    let sin2_t = eta * eta * (1. - cos_i * cos_i);
+    if sin2_t >= 1. {
+        return (1., None);
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let rs = (n1 * cos_i - n2 * cos_t) / (n1 * cos_i + n2 * cos_t);
+    let rp = (n1 * cos_t - n2 * cos_i) / (n1 * cos_t + n2 * cos_i);
+    let refracted = dir.mulf(eta).add(&normal.mulf(eta * cos_i - cos_t));
+    ((rs * rs + rp * rp) / 2., Some(refracted.normalized()))
+}
+
+// Share of the light a rough surface reflects instead of scattering it diffusely.
+fn reflected_back(roughness: u8) -> f64 {
+    if roughness == 255 {
+        return 0.;
+    }
+    1. - (1. / (2_f64).powf(4. - roughness as f64 / 64.))
+}
+
+// Samples the reflection of a ray off a GGX microfacet surface with the width given by
+// `roughness`. Returns the reflected direction and its weight, brdf * cos / pdf, or
+// None if the sampled microfacet reflects the ray into the surface. Very smooth
+// surfaces are perfect mirrors.
+fn glossy_reflection(
+    dir: &Vec3,
+    normal: &Vec3,
+    roughness: u8,
+    rng: &mut Rng,
+) -> Option<(Vec3, f64)> {
+    let alpha = ggx_alpha(roughness);
+    if alpha < MIRROR_ALPHA || normal.len() == 0. {
+        return Some((reflect(dir, normal), 1.));
+    }
+    let h = ggx_half_vector(normal, alpha, rng);
+    let reflection = reflect(dir, &h);
+    let n_dot_v = -dir.dot(normal);
+    let n_dot_l = reflection.dot(normal);
+    if n_dot_v <= 0. || n_dot_l <= 0. {
+        return None;
+    }
+    // with h sampled by D(h) * cos(theta_h), D and the jacobian cancel out
+    let g = ggx_g1(n_dot_v, alpha) * ggx_g1(n_dot_l, alpha);
+    Some((reflection, g * -dir.dot(&h) / (n_dot_v * h.dot(normal))))
+}
+
+// Moves off the surface so rays leaving it don't hit the same voxel again.
+fn push_off_surface(position: &Vec3, normal: &Vec3, dir: &Vec3) -> Vec3 {
+    let push = if normal.len() > 0. {
+        *normal
+    } else {
+        dir.mulf(-1.)
+    };
+    position.add(&push.mulf(SOLID_POS_PUSH))
+}
+
+// Light a white diffuse surface at `position` reflects from the lights in the lighting
+// tree: their irradiance over PI, the Lambertian brdf. With `settings.light_samples` set,
+// only that many emissive voxels are picked, see `select_lights`.
+fn direct_light(
+    position: &Vec3,
+    normal: &Vec3,
+    scene: &Scene,
+    settings: &RenderSettings,
+    rng: &mut Rng,
+) -> Vec3 {
+    let mut color = Vec3::new(0., 0., 0.);
+    if settings.light_samples == 0 {
+        scene.lights.query(position, |light, _| {
+            let light = area_light(position, normal, light, scene, settings.shadow_samples, rng);
+            color = color.add(&light);
+        });
+    } else {
+        for (light, weight) in select_lights(position, normal, scene, settings.light_samples, rng) {
+            let light = area_light(
+                position,
+                normal,
+                &light,
+                scene,
+                settings.shadow_samples,
+                rng,
+            );
+            color = color.add(&light.mulf(weight));
+        }
+    }
+    for light in scene.lights.analytic() {
+        let light = analytic_light(position, normal, light, scene, settings.shadow_samples, rng);
+        color = color.add(&light);
+    }
+    color.mulf(FRAC_1_PI)
+}
+
+/// Picks up to `count` of the emissive voxels relevant for `position`, with a
+/// probability proportional to their emission over the squared distance. Voxels which
+/// lie completely behind the surface are never picked. Returns the picked lights with
+/// the weight 1 / (count * probability), so the weighted sum of their light is an
+/// unbiased estimate of the light of all of them. If there are no more than `count`
+/// candidates, every one is returned once with the weight 1.
+pub fn select_lights(
+    position: &Vec3,
+    normal: &Vec3,
+    scene: &Scene,
+    count: usize,
+    rng: &mut Rng,
+) -> Vec<(Vec3, f64)> {
+    // candidates with the running sum of their weights
+    let mut cdf: Vec<(Vec3, f64)> = vec![];
+    let mut total = 0.;
+    scene.lights.query(position, |light, emission| {
+        let center = light.voxel_center();
+        let to_light = center.sub(position);
+        // the corner of the voxel furthest in front of the surface
+        let reach = (normal.x.abs() + normal.y.abs() + normal.z.abs()) / 2.;
+        if normal.len() > 0. && to_light.dot(normal) + reach <= 0. {
+            return;
+        }
+        // at least the distance of a face, so lights right next to the surface don't
+        // take all samples
+        let r2 = to_light.dot(&to_light).max(0.25);
+        total += emission_strength_from_u8(emission) / r2;
+        cdf.push((*light, total));
+    });
+    if cdf.len() <= count {
+        return cdf.into_iter().map(|(light, _)| (light, 1.)).collect();
+    }
+    (0..count)
+        .map(|i| {
+            // stratified over the cdf, one pick in every 1 / count of it
+            let u = (i as f64 + rng.float()) / count as f64 * total;
+            let picked = cdf.partition_point(|&(_, c)| c <= u).min(cdf.len() - 1);
+            let below = if picked == 0 { 0. } else { cdf[picked - 1].1 };
+            let probability = (cdf[picked].1 - below) / total;
+            (cdf[picked].0, 1. / (count as f64 * probability))
+        })
+        .collect()
+}
+
+/// Light arriving at `position` from a light which is not made of voxels, weighted by
+/// the cosine to `normal`. The sun gets `samples` shadow rays spread over its disc,
+/// point and spot lights a single one. Like for area lights, shadow rays pass through
+/// glass.
+pub fn analytic_light(
+    position: &Vec3,
+    normal: &Vec3,
+    light: &Light,
+    scene: &Scene,
+    samples: usize,
+    rng: &mut Rng,
+) -> Vec3 {
+    let samples = match light {
+        Light::Sun { .. } => samples,
+        _ => 1,
+    };
+    let mut color = Vec3::new(0., 0., 0.);
+    for i in 0..samples {
+        let sample = light.sample(position, i, samples, rng);
+        let cos_surface = normal.dot(&sample.dir);
+        if cos_surface <= 0. {
+            continue;
+        }
+        let (hit, transmittance) = cast_shadow(position, &sample.dir, scene);
+        if hit.voxel.is_some() && hit.position.sub(position).len() < sample.distance {
+            continue;
+        }
+        let weight = cos_surface / samples as f64;
+        color = color.add(&sample.irradiance.mul(&transmittance).mulf(weight));
+    }
+    color
+}
+
+// Environment light arriving at `position`, averaged over `settings.sky_samples`
+// cosine weighted rays. This is the light a diffuse bounce of the path tracer picks up
+// from the environment, so both integrators agree on it.
+fn sky_light(
+    position: &Vec3,
+    normal: &Vec3,
+    scene: &Scene,
+    settings: &RenderSettings,
+    rng: &mut Rng,
+) -> Vec3 {
+    let mut color = Vec3::new(0., 0., 0.);
+    if scene.environment.is_black() || normal.len() == 0. {
+        return color;
+    }
+    for _ in 0..settings.sky_samples {
+        let dir = cosine_hemisphere(normal, rng);
+        let (hit, transmittance) = cast_shadow(position, &dir, scene);
+        if hit.voxel.is_some() {
+            continue;
+        }
+        let sky = scene.environment.radiance(&dir).mul(&transmittance);
+        color = color.add(&sky.mulf(1. / settings.sky_samples as f64));
+    }
+    color
+}
+
+/// Light arriving at `position` from the emissive voxel at `light`, weighted by the
+/// cosine to `normal`. The voxel is treated as an area light made of its faces which
+/// look towards `position`: every face gets `samples` shadow rays, stratified over the
+/// face, and each unoccluded ray adds the emitted light times its solid angle
+/// (cos_surface * cos_light / r^2 per unit of area). A single sample goes through
+/// the center of the face. Shadow rays pass straight through glass, which only
+/// absorbs some of the light.
+pub fn area_light(
+    position: &Vec3,
+    normal: &Vec3,
+    light: &Vec3,
+    scene: &Scene,
+    samples: usize,
+    rng: &mut Rng,
+) -> Vec3 {
+    let mut color = Vec3::new(0., 0., 0.);
+    let lo = light.voxel_center().sub(&Vec3::new(0.5, 0.5, 0.5));
+    let p = [position.x, position.y, position.z];
+    let l = [lo.x, lo.y, lo.z];
+    for axis in 0..3 {
+        // only the faces facing the position can be seen from it
+        let plane = if p[axis] < l[axis] {
+            l[axis]
+        } else if p[axis] > l[axis] + 1. {
+            l[axis] + 1.
+        } else {
+            continue;
+        };
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        for i in 0..samples {
+            let (su, sv) = if samples == 1 {
+                (0.5, 0.5)
+            } else {
+                stratified(i, samples, rng)
+            };
+            let mut q = [0.; 3];
+            q[axis] = plane;
+            q[u] = l[u] + su;
+            q[v] = l[v] + sv;
+            let to_light = Vec3::new(q[0], q[1], q[2]).sub(position);
+            let r2 = to_light.dot(&to_light);
+            let dir = to_light.mulf(1. / r2.sqrt());
+            let cos_surface = normal.dot(&dir);
+            if cos_surface <= 0. {
+                continue;
+            }
+            let cos_light = [dir.x, dir.y, dir.z][axis].abs();
+            let (light_hit, transmittance) = cast_shadow(position, &dir, scene);
+            // anything else in the way, including the edge of a neighbouring voxel
+            // on a grazing ray, just casts a shadow
+            let Some(&VoxelMaterial::Emission {
+                color: light_color,
+                emission,
+            }) = light_hit.voxel
+            else {
+                continue;
+            };
+            if !light_hit.bounds.containsf(&light.voxel_center()) {
+                continue;
+            }
+            let weight = cos_surface * cos_light / r2 / samples as f64;
+            let emitted = color_to_linear(&light_color).mulf(emission_strength_from_u8(emission));
+            color = color.add(&emitted.mul(&transmittance).mulf(weight));
+        }
+    }
+    color
+}
+
+/// Monte Carlo estimate of the light seen along a ray, following paths of up to
+/// `settings.bounces` surfaces. Diffuse surfaces get the direct light of the lighting tree
+/// plus a cosine weighted bounce, glossy reflections are picked with the same share
+/// `direct_color` mixes them in. Emitters are only counted when they are seen
+/// directly or in a reflection, diffuse bounces which hit them are already covered by
+/// the direct light. Glass reflects or refracts with the Fresnel probability and absorbs
+/// light along the way through it. Paths which leave the octree pick up the light of
+/// the environment. Paths are terminated early by russian roulette.
+pub fn path_color(
+    origin: &Vec3,
+    dir: &Vec3,
+    scene: &Scene,
+    settings: &RenderSettings,
+    rng: &mut Rng,
+) -> Vec3 {
+    trace_path(origin, dir, None, scene, settings, rng)
+}
+
+// `path_color` starting with `first`, the hit of the ray if it was already cast.
+fn trace_path(
+    origin: &Vec3,
+    dir: &Vec3,
+    mut first: Option<&CastHit<VoxelMaterial>>,
+    scene: &Scene,
+    settings: &RenderSettings,
+    rng: &mut Rng,
+) -> Vec3 {
+    let mut color = Vec3::new(0., 0., 0.);
+    let mut throughput = Vec3::new(1., 1., 1.);
+    let mut origin = *origin;
+    let mut dir = *dir;
+    let mut count_emission = true;
+    // glass the path currently travels through
+    let mut medium: Option<Medium> = None;
+    for depth in 0..settings.bounces {
+        let cast;
+        let hit = match (first.take(), &medium) {
+            (Some(first), _) => first,
+            (None, Some(medium)) => {
+                cast = medium.exit(&origin, &dir, scene);
+                &cast
+            }
+            (None, None) => {
+                cast = cast_to_hit(origin, &dir, &scene.tree);
+                &cast
+            }
+        };
+        let normal = hit.normal;
+        if let Some(inside) = &medium {
+            throughput = throughput.mul(&inside.transmittance(hit.distance));
+            if hit.status != CastStatus::Hit {
+                let sky = scene.environment.radiance(&dir);
+                color = color.add(&throughput.mul(&sky));
+                break;
+            }
+            let (fresnel, refracted) = fresnel_refract(&dir, &normal, inside.ior, 1.);
+            match refracted {
+                Some(refracted) if rng.float() >= fresnel => {
+                    dir = refracted;
+                    medium = None;
+                    if hit.voxel.is_none() {
+                        origin = hit.position.sub(&normal.mulf(SOLID_POS_PUSH));
+                        continue;
+                    }
+                    // otherwise the ray leaves straight into the voxel it hit
+                }
+                _ => {
+                    origin = hit.position.add(&normal.mulf(SOLID_POS_PUSH));
+                    dir = reflect(&dir, &normal);
+                    count_emission = true;
+                    continue;
+                }
+            }
+        }
+        let Some(&voxel) = hit.voxel else {
+            let sky = scene.environment.radiance(&dir);
+            color = color.add(&throughput.mul(&sky));
+            break;
+        };
+        match voxel {
+            VoxelMaterial::Emission { color: c, emission } => {
+                if count_emission {
+                    let emitted = color_to_linear(&c).mulf(emission_strength_from_u8(emission));
+                    color = color.add(&throughput.mul(&emitted));
+                }
+                break;
+            }
+            VoxelMaterial::Rough { roughness, .. } => {
+                let albedo = voxel.color();
+                origin = push_off_surface(&hit.position, &normal, &dir);
+                if rng.float() < reflected_back(roughness) {
+                    let Some((reflection, weight)) =
+                        glossy_reflection(&dir, &normal, roughness, rng)
+                    else {
+                        break;
+                    };
+                    dir = reflection;
+                    throughput = throughput.mulf(weight);
+                    count_emission = true;
+                } else {
+                    let direct = direct_light(&origin, &normal, scene, settings, rng).mul(&albedo);
+                    color = color.add(&throughput.mul(&direct));
+                    if normal.len() == 0. {
+                        break;
+                    }
+                    // the cosine and 1 / PI of the brdf cancel with the pdf
+                    dir = cosine_hemisphere(&normal, rng);
+                    throughput = throughput.mul(&albedo);
+                    count_emission = false;
+                }
+            }
+            VoxelMaterial::Glass { ior, .. } => {
+                // refracted rays keep `count_emission`, shadow rays pass through glass
+                // and already counted the lights behind it
+                if normal.len() == 0. {
+                    // the path starts inside of the glass
+                    origin = hit.position;
+                    medium = Medium::new(&voxel);
+                    continue;
+                }
+                let (fresnel, refracted) = fresnel_refract(&dir, &normal, 1., ior as f64);
+                match refracted {
+                    Some(refracted) if rng.float() >= fresnel => {
+                        origin = hit.position.sub(&normal.mulf(SOLID_POS_PUSH));
+                        dir = refracted;
+                        medium = Medium::new(&voxel);
+                    }
+                    _ => {
+                        origin = hit.position.add(&normal.mulf(SOLID_POS_PUSH));
+                        dir = reflect(&dir, &normal);
+                        count_emission = true;
+                    }
+                }
+            }
+        }
+        if depth >= RUSSIAN_ROULETTE_DEPTH {
+            let survive = throughput.x.max(throughput.y).max(throughput.z).min(0.95);
+            if rng.float() >= survive {
+                break;
+            }
+            throughput = throughput.mulf(1. / survive);
+        }
+    }
+    color
+}
+
+/// How the color of a camera ray is computed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Integrator {
+    /// Direct light and glossy reflections, see [`direct_color`].
+    Direct,
+    /// Monte Carlo path tracing with indirect diffuse light, see [`path_color`].
+    Path,
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "direct" => Ok(Integrator::Direct),
+            "path" => Ok(Integrator::Path),
+            _ => Err(format!("unknown integrator `{s}`, expected direct or path")),
+        }
+    }
+}
+
+/// Everything about a render which is not part of the scene.
+pub struct RenderSettings {
+    /// Image width in pixels.
+    pub width: u32,
+    /// Image height in pixels.
+    pub height: u32,
+    /// Maximum number of surfaces a camera ray may bounce off.
+    pub bounces: usize,
+    /// How the image is displayed when it is saved in an 8 bit format.
+    pub tone_mapper: ToneMapper,
+    /// How the color of a camera ray is computed.
+    pub integrator: Integrator,
+    /// Camera rays per pixel, jittered over the footprint of `filter`.
+    pub samples: usize,
+    /// Weights of the samples of a pixel.
+    pub filter: PixelFilter,
+    /// Shadow rays per visible face of every emissive voxel.
+    pub shadow_samples: usize,
+    /// Reflection rays of glossy surfaces seen by the camera in the direct integrator.
+    pub gloss_samples: usize,
+    /// Rays towards the environment per rough surface in the direct integrator.
+    pub sky_samples: usize,
+    /// Emissive voxels picked per shading point, 0 uses every relevant one.
+    pub light_samples: usize,
+    /// Seed of the subpixel positions and of the path tracer.
+    pub seed: u64,
+}
+
+/// Side length of the square tiles the image is split into.
+pub const TILE_SIZE: u32 = 32;
+
+/// Rectangle of pixels rendered as one unit of work.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    /// Left column of the tile.
+    pub x: u32,
+    /// Top row of the tile.
+    pub y: u32,
+    /// Width in pixels.
+    pub width: u32,
+    /// Height in pixels.
+    pub height: u32,
+}
+
+/// Splits the image into tiles of at most `TILE_SIZE` pixels, row by row.
+pub fn tiles(width: u32, height: u32) -> Vec<Tile> {
+    let mut tiles = vec![];
+    for y in (0..height).step_by(TILE_SIZE as usize) {
+        for x in (0..width).step_by(TILE_SIZE as usize) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: TILE_SIZE.min(width - x),
+                height: TILE_SIZE.min(height - y),
+            });
+        }
+    }
+    tiles
+}
+
+/// Renders the scene on `threads` threads and returns the linear radiance of the pixels
+/// row by row, [`crate::output`] tone maps or saves it as it is.
+/// Workers take the next unrendered tile from a shared counter, so expensive regions
+/// don't stall a single thread. Every pixel is computed independently of the thread
+/// which renders it, so the result doesn't depend on `threads`.
+pub fn render_image(scene: &Scene, settings: &RenderSettings, threads: usize) -> Vec<Vec3> {
+    render_passes(scene, settings, &[], threads).0
+}
+
+/// Renders the image like [`render_image`] and collects the passes `aovs` from the
+/// first surface its camera rays hit on the way, so they line up with the image and
+/// don't cost extra rays. Returns the image and one buffer per pass.
+pub fn render_passes(
+    scene: &Scene,
+    settings: &RenderSettings,
+    aovs: &[Aov],
+    threads: usize,
+) -> (Vec<Vec3>, Vec<Vec<Vec3>>) {
+    let channels = 1 + aovs.len();
+    let data = render_tiles(settings, threads, channels, |tile| {
+        render_tile_passes(tile, scene, settings, aovs)
+    });
+    let mut passes =
+        (0..channels).map(|i| data.iter().skip(i).step_by(channels).copied().collect());
+    let image = passes.next().unwrap_or_default();
+    (image, passes.collect())
+}
+
+// Shared scheduling of the renders. `render` returns `channels`
+// values per pixel of a tile, row by row, which end up interleaved in the result.
+fn render_tiles<F>(
+    settings: &RenderSettings,
+    threads: usize,
+    channels: usize,
+    render: F,
+) -> Vec<Vec3>
+where
+    F: Fn(&Tile) -> Vec<Vec3> + Sync,
+{
+    let (w, h) = (settings.width, settings.height);
+    let tiles = tiles(w, h);
+    let next = AtomicUsize::new(0);
+    let rendered: Vec<(Tile, Vec<Vec3>)> = thread::scope(|s| {
+        let workers: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = vec![];
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(&tile) = tiles.get(i) else {
+                            return done;
+                        };
+                        done.push((tile, render(&tile)));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+
+    let mut data = vec![Vec3::new(0., 0., 0.); (w * h) as usize * channels];
+    for (tile, pixels) in rendered {
+        for (row, line) in pixels.chunks(tile.width as usize * channels).enumerate() {
+            let start = ((tile.y + row as u32) * w + tile.x) as usize * channels;
+            data[start..start + line.len()].copy_from_slice(line);
+        }
+    }
+    data
+}
+
+/// Renders a single tile and returns the linear radiance of its pixels row by row.
+pub fn render_tile(tile: &Tile, scene: &Scene, settings: &RenderSettings) -> Vec<Vec3> {
+    render_tile_passes(tile, scene, settings, &[])
+}
+
+// The pixels of a tile row by row, each followed by its `aovs`.
+fn render_tile_passes(
+    tile: &Tile,
+    scene: &Scene,
+    settings: &RenderSettings,
+    aovs: &[Aov],
+) -> Vec<Vec3> {
+    let camera = tile_camera(scene, settings);
+
+    let mut buf = Vec::with_capacity((tile.width * tile.height) as usize * (1 + aovs.len()));
+    for y in tile.y..tile.y + tile.height {
+        for x in tile.x..tile.x + tile.width {
+            pixel_color(x, y, &camera, scene, settings, aovs, &mut buf);
+        }
+    }
+    buf
+}
+
+/// How much a render with the light limits of the scene differs from one with every
+/// light, see [`compare_culling`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CullingReport {
+    /// Largest difference of a pixel channel in linear radiance.
+    pub max_error: f64,
+    /// Pixel with the largest difference.
+    pub max_error_at: (u32, u32),
+    /// Largest difference of a pixel channel after tone mapping, in 8 bit steps.
+    pub max_display_error: u8,
+    /// Share of the light of every light which is left with the limits.
+    pub energy: f64,
+}
+
+impl CullingReport {
+    /// Compares two renders of `settings.width` x `settings.height` pixels.
+    pub fn compare(culled: &[Vec3], brute_force: &[Vec3], settings: &RenderSettings) -> Self {
+        let mut report = CullingReport {
+            max_error: 0.,
+            max_error_at: (0, 0),
+            max_display_error: 0,
+            energy: 1.,
+        };
+        let (mut culled_sum, mut brute_force_sum) = (0., 0.);
+        for (i, (c, b)) in culled.iter().zip(brute_force).enumerate() {
+            let d = b.sub(c);
+            let error = d.x.abs().max(d.y.abs()).max(d.z.abs());
+            if error > report.max_error {
+                report.max_error = error;
+                let i = i as u32;
+                report.max_error_at = (i % settings.width, i / settings.width);
+            }
+            let (c8, b8) = (
+                f_to_color(c, &settings.tone_mapper),
+                f_to_color(b, &settings.tone_mapper),
+            );
+            for (c8, b8) in c8.into_iter().zip(b8) {
+                report.max_display_error = report.max_display_error.max(c8.abs_diff(b8));
+            }
+            culled_sum += c.x + c.y + c.z;
+            brute_force_sum += b.x + b.y + b.z;
+        }
+        if brute_force_sum > 0. {
+            report.energy = culled_sum / brute_force_sum;
+        }
+        report
+    }
+}
+
+impl fmt::Display for CullingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (x, y) = self.max_error_at;
+        write!(
+            f,
+            "max error {:.5} at ({x}, {y}), {} of 255 after tone mapping, {:.3}% of the energy lost",
+            self.max_error,
+            self.max_display_error,
+            (1. - self.energy) * 100.
+        )
+    }
+}
+
+/// Renders the scene again with every light in reach of every point and compares it
+/// to `culled`, a render with the current light limits. Stochastic settings like
+/// `light_samples` or more than one shadow sample add their noise to the error.
+pub fn compare_culling(
+    scene: &mut Scene,
+    settings: &RenderSettings,
+    threads: usize,
+    culled: &[Vec3],
+) -> CullingReport {
+    let limits = scene.lights.limits();
+    scene.lights.set_limits(LightLimits::BRUTE_FORCE);
+    let brute_force = render_image(scene, settings, threads);
+    scene.lights.set_limits(limits);
+    CullingReport::compare(culled, &brute_force, settings)
+}
+
+fn tile_camera(scene: &Scene, settings: &RenderSettings) -> Camera {
+    let mut camera = scene.camera;
+    camera.aspect = settings.width as f64 / settings.height as f64;
+    camera
+}
+
+// Filtered average of `settings.samples` rays through the pixel (x, y), pushed to `buf`
+// followed by the `aovs` of the surfaces the rays hit.
+fn pixel_color(
+    x: u32,
+    y: u32,
+    camera: &Camera,
+    scene: &Scene,
+    settings: &RenderSettings,
+    aovs: &[Aov],
+    buf: &mut Vec<Vec3>,
+) {
+    let mut pixel_rng = Rng::for_pixel(settings.seed, x, y);
+    // separate stream so the subpixel positions don't depend on the integrator
+    let mut rng = Rng::new(pixel_rng.next_u64());
+    let forward = camera.forward();
+    let mut color = Vec3::new(0., 0., 0.);
+    let mut values = vec![Vec3::new(0., 0., 0.); aovs.len()];
+    let mut max_weight = 0.;
+    let weights = camera_samples(
+        x,
+        y,
+        camera,
+        settings,
+        &mut pixel_rng,
+        |origin, dir, weight| {
+            let hit = cast_to_hit(*origin, dir, &scene.tree);
+            let sample = match settings.integrator {
+                Integrator::Direct if settings.bounces == 0 => Vec3::new(0., 0., 0.),
+                Integrator::Direct => {
+                    shade_direct(&hit, dir, scene, settings, settings.bounces, &mut rng)
+                }
+                Integrator::Path => trace_path(origin, dir, Some(&hit), scene, settings, &mut rng),
+            };
+            color = color.add(&sample.mulf(weight));
+
+            let central = weight > max_weight;
+            max_weight = f64::max(max_weight, weight);
+            for (value, aov) in values.iter_mut().zip(aovs) {
+                let sample = aov.sample(&hit, dir, &forward, scene);
+                if aov.is_filtered() {
+                    *value = value.add(&sample.mulf(weight));
+                } else if central {
+                    *value = sample;
+                }
+            }
+        },
+    );
+    if weights == 0. {
+        buf.push(color);
+        buf.extend(values);
+        return;
+    }
+    buf.push(color.mulf(1. / weights));
+    for (value, aov) in values.iter_mut().zip(aovs) {
+        if aov.is_filtered() {
+            *value = value.mulf(1. / weights);
+        }
+    }
+    buf.extend(values);
+}
+
+// Calls `f` with the origin, direction and filter weight of `settings.samples` camera
+// rays spread over the filter footprint around the center of the pixel (x, y), and
+// returns the sum of the weights. A single sample goes through the center.
+fn camera_samples<F>(
+    x: u32,
+    y: u32,
+    camera: &Camera,
+    settings: &RenderSettings,
+    pixel_rng: &mut Rng,
+    mut f: F,
+) -> f64
+where
+    F: FnMut(&Vec3, &Vec3, f64),
+{
+    let radius = settings.filter.radius();
+    let mut weights = 0.;
+    for i in 0..settings.samples {
+        let (dx, dy) = if settings.samples == 1 {
+            (0., 0.)
+        } else {
+            let (sx, sy) = stratified(i, settings.samples, pixel_rng);
+            ((2. * sx - 1.) * radius, (2. * sy - 1.) * radius)
+        };
+        let weight = settings.filter.weight(dx, dy);
+        if weight <= 0. {
+            continue;
+        }
+        let u = (x as f64 + 0.5 + dx) / settings.width as f64;
+        let v = (y as f64 + 0.5 + dy) / settings.height as f64;
+        let (origin, dir) = camera.ray(u, v);
+        f(&origin, &dir, weight);
+        weights += weight;
+    }
+    weights
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+    use crate::{
+        material::srgb_to_linear,
+        math::Cube,
+        test_util::{assert_close, unit_vec},
+    };
+
+    #[test]
+    fn tiles_cover_image_once() {
+        let (w, h) = (77, 40);
+        let mut covered = vec![0; (w * h) as usize];
+        for tile in tiles(w, h) {
+            assert!(tile.width > 0 && tile.width <= TILE_SIZE);
+            assert!(tile.height > 0 && tile.height <= TILE_SIZE);
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    covered[(y * w + x) as usize] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&c| c == 1));
+    }
+
+    const TEST_SCENE: &str = "bounds -16 -16 -16 32\n\
+        camera -6 -6 -4 0 0 0\n\
+        box -8 -8 0 8 8 0 rough 200 200 200 200\n\
+        box -2 -2 -3 1 1 -1 rough 200 40 40 100\n\
+        voxel 3 -3 -5 emission 255 230 200 90\n";
+
+    fn settings(integrator: Integrator) -> RenderSettings {
+        RenderSettings {
+            width: 70,
+            height: 45,
+            bounces: 3,
+            tone_mapper: ToneMapper::default(),
+            integrator,
+            samples: 4,
+            filter: PixelFilter::Tent,
+            shadow_samples: 1,
+            gloss_samples: 1,
+            sky_samples: 1,
+            light_samples: 0,
+            seed: 0,
+        }
+    }
+
+    #[test]
+    fn output_does_not_depend_on_thread_count() {
+        let scene = Scene::parse(TEST_SCENE).unwrap();
+        for integrator in [Integrator::Direct, Integrator::Path] {
+            let settings = settings(integrator);
+            let single = render_image(&scene, &settings, 1);
+            assert!(single.iter().any(|c| c.len() > 0.));
+            for threads in [2, 3, 8] {
+                assert!(render_image(&scene, &settings, threads) == single);
+            }
+        }
+    }
+
+    #[test]
+    fn path_tracing_adds_indirect_light() {
+        // a diffuse floor under a lamp: bounced rays escape or hit the lamp, which is
+        // already covered by the direct light, so both integrators must agree
+        let open = Scene::parse(
+            "bounds -16 -16 -16 32\n\
+             camera -6 -6 -4 0 0 0\n\
+             box -8 -8 0 8 8 0 rough 200 200 200 255\n\
+             voxel 0 0 -4 emission 255 230 200 90\n",
+        )
+        .unwrap();
+        assert!(
+            render_image(&open, &settings(Integrator::Path), 1)
+                == render_image(&open, &settings(Integrator::Direct), 1)
+        );
+
+        // with a wall light bounces back, which only the path tracer sees
+        let mut settings = settings(Integrator::Path);
+        settings.bounces = 6;
+        let walled = Scene::parse(
+            "bounds -16 -16 -16 32\n\
+             camera -6 -6 -4 0 0 0\n\
+             box -8 -8 0 8 8 0 rough 200 200 200 255\n\
+             box 4 -8 -8 4 8 0 rough 255 255 255 255\n\
+             voxel 0 0 -4 emission 255 230 200 90\n",
+        )
+        .unwrap();
+        let path = render_image(&walled, &settings, 1);
+        settings.integrator = Integrator::Direct;
+        let direct = render_image(&walled, &settings, 1);
+        let brightness = |img: &[Vec3]| -> f64 { img.iter().map(|c| c.x + c.y + c.z).sum() };
+        assert!(path
+            .iter()
+            .zip(&direct)
+            .all(|(p, d)| p.x >= d.x && p.y >= d.y && p.z >= d.z));
+        assert!(brightness(&path) > brightness(&direct));
+    }
+
+    #[test]
+    fn emitters_light_like_the_sky() {
+        // a white floor reflects the radiance of a constant sky, a ceiling of lights
+        // with the same radiance covers almost all of its hemisphere
+        let floor = "bounds -64 -64 -64 128\n\
+            box -32 -32 0 32 32 0 rough 255 255 255 255\n";
+        let sky = Scene::parse(&format!("{floor}sky color 255 255 255 1\n")).unwrap();
+        let lamps = Scene::parse(&format!(
+            "{floor}light_limits 0 0\nbox -32 -32 -3 32 32 -3 emission 255 255 255 0\n"
+        ))
+        .unwrap();
+        let mut settings = settings(Integrator::Direct);
+        settings.shadow_samples = 4;
+        settings.sky_samples = 16;
+        let shade = |scene: &Scene| {
+            let mut rng = Rng::new(1);
+            let origin = Vec3::new(0.5, 0.5, -1.);
+            direct_color(&origin, &Vec3::new(0., 0., 1.), scene, &settings, &mut rng)
+        };
+        let expected = shade(&sky);
+        assert_close(&expected, &Vec3::new(1., 1., 1.));
+        let lit = shade(&lamps);
+        assert!((lit.x - expected.x).abs() < 0.02, "{lit:?}");
+    }
+
+    #[test]
+    fn light_bounces_in_a_closed_box_add_up() {
+        // a point light inside a closed diffuse box: the walls receive its power once
+        // directly and then again after every bounce, in total power / (1 - albedo),
+        // so their average radiance is 4 * albedo * intensity / (area * (1 - albedo))
+        let mut scene = Scene::new(Cube::new(-16., -16., -16., 32.));
+        let wall = VoxelMaterial::rough([188, 188, 188], 255);
+        scene.fill_box(Vec3::newi(-5, -5, -5), Vec3::newi(4, 4, 4), wall);
+        scene.clear_box(Vec3::newi(-4, -4, -4), Vec3::newi(3, 3, 3));
+        let intensity = 40.;
+        scene.add_light(Light::Point {
+            position: Vec3::new(0., 0., 0.),
+            intensity: Vec3::new(1., 1., 1.).mulf(intensity),
+        });
+        let mut settings = settings(Integrator::Path);
+        settings.bounces = 64;
+        let albedo = srgb_to_linear(188);
+        let area = 6. * 8. * 8.;
+        let expected = 4. * albedo * intensity / (area * (1. - albedo));
+
+        // paths leave the center in uniform directions, each one weighted by the wall
+        // area it covers so that the mean is an average over the walls
+        let origin = Vec3::new(0., 0., 0.);
+        let mut rng = Rng::new(5);
+        let paths = 4000;
+        let mut mean = 0.;
+        for _ in 0..paths {
+            let dir = unit_vec(&mut rng);
+            let hit = cast_to_hit(origin, &dir, &scene.tree);
+            let area_weight = hit.distance.powi(2) / dir.dot(&hit.normal).abs() * 4. * PI;
+            let radiance = path_color(&origin, &dir, &scene, &settings, &mut rng);
+            mean += radiance.x * area_weight / area / paths as f64;
+        }
+        assert!(
+            (mean - expected).abs() < 0.03 * expected,
+            "{mean} != {expected}"
+        );
+    }
+
+    #[test]
+    fn analytic_lights_are_reflected_diffusely() {
+        // a white floor reflects the irradiance of a light over PI
+        let floor = "bounds -16 -16 -16 32\nbox -8 -8 0 8 8 0 rough 255 255 255 255\n";
+        let settings = settings(Integrator::Direct);
+        let origin = Vec3::new(0.5, 0.5, -4.);
+        let down = Vec3::new(0., 0., 1.);
+        for light in [
+            "sun 0 0 -1 0.5 255 255 255 2",
+            "point 0.5 0.5 -2 255 255 255 8",
+        ] {
+            let scene = Scene::parse(&format!("{floor}light {light}\n")).unwrap();
+            let color = direct_color(&origin, &down, &scene, &settings, &mut Rng::new(1));
+            // the shading point is pushed off the floor a little
+            assert!(
+                (color.x - 2. * FRAC_1_PI).abs() < 1e-3,
+                "{light}: {color:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn supersampling_blends_edges() {
+        // an emissive half plane seen from straight above, offset by a third of a
+        // pixel so its edge runs through a column of pixels
+        let scene = Scene::parse(
+            "bounds -16 -16 -16 32\n\
+             camera 0.33 0 -8 0.33 0 0\n\
+             camera_up 0 1 0\n\
+             orthographic 8\n\
+             box -16 -16 0 -1 15 0 emission 255 255 255 0\n",
+        )
+        .unwrap();
+        let mut settings = settings(Integrator::Direct);
+        settings.width = 8;
+        settings.height = 8;
+        let distinct = |settings: &RenderSettings| {
+            let mut colors: Vec<_> = render_image(&scene, settings, 1)
+                .iter()
+                .map(|c| [c.x, c.y, c.z].map(f64::to_bits))
+                .collect();
+            colors.sort();
+            colors.dedup();
+            colors.len()
+        };
+        settings.samples = 1;
+        assert_eq!(distinct(&settings), 2);
+        settings.samples = 16;
+        for filter in [PixelFilter::Box, PixelFilter::Tent, PixelFilter::Gaussian] {
+            settings.filter = filter;
+            assert!(distinct(&settings) > 2, "{filter:?}");
+        }
+    }
+
+    #[test]
+    fn area_light_matches_solid_angle() {
+        let scene =
+            Scene::parse("bounds -16 -16 -16 32\nvoxel 0 0 0 emission 255 255 255 0\n").unwrap();
+        let mut rng = Rng::new(1);
+        let up = Vec3::new(0., 0., 1.);
+        for d in [0.5, 1., 4.] {
+            let position = Vec3::new(0.5, 0.5, -d);
+            let light = area_light(&position, &up, &Vec3::newi(0, 0, 0), &scene, 256, &mut rng);
+            // integral of cos * cos / r^2 over a unit square centered below the point
+            let a = 0.5 / d / (1. + 0.25 / (d * d)).sqrt();
+            let expected = 4. * a * a.atan();
+            assert!(
+                (light.x - expected).abs() < 0.01 * expected,
+                "{d}: {light:?} != {expected}"
+            );
+        }
+        // seen from a corner three faces contribute
+        let position = Vec3::new(-3., -3., -3.);
+        let towards = Vec3::new(1., 1., 1.).normalized();
+        let light = area_light(
+            &position,
+            &towards,
+            &Vec3::newi(0, 0, 0),
+            &scene,
+            64,
+            &mut rng,
+        );
+        let single_face = area_light(
+            &position,
+            &towards,
+            &Vec3::newi(0, 0, 0),
+            &scene,
+            1,
+            &mut rng,
+        );
+        assert!((light.x - single_face.x).abs() < 0.01 * light.x);
+        assert!(light.x > 3. * towards.z / (3.5 * 3.5 * 3.) * 0.9);
+    }
+
+    #[test]
+    fn area_light_casts_soft_shadows() {
+        // an occluder between the light and the points below it, ending at x = 1
+        let open =
+            Scene::parse("bounds -16 -16 -16 32\nvoxel 0 0 0 emission 255 255 255 0\n").unwrap();
+        let occluded = Scene::parse(
+            "bounds -16 -16 -16 32\n\
+             voxel 0 0 0 emission 255 255 255 0\n\
+             box -4 -4 2 0 4 2 rough 10 10 10 255\n",
+        )
+        .unwrap();
+        let up = Vec3::new(0., 0., -1.);
+        let light = |scene: &Scene, x: f64| {
+            let position = Vec3::new(x, 0.5, 5.);
+            area_light(
+                &position,
+                &up,
+                &Vec3::newi(0, 0, 0),
+                scene,
+                64,
+                &mut Rng::new(1),
+            )
+            .x
+        };
+        // fully hidden, fully visible and partially visible
+        assert_eq!(light(&occluded, -6.), 0.);
+        assert_eq!(light(&occluded, 5.), light(&open, 5.));
+        let (penumbra, lit) = (light(&occluded, 2.), light(&open, 2.));
+        assert!(
+            penumbra > 0.1 * lit && penumbra < 0.9 * lit,
+            "{penumbra} {lit}"
+        );
+    }
+
+    #[test]
+    fn glossy_reflection_conserves_energy() {
+        let mut rng = Rng::new(2);
+        let normal = Vec3::new(0., 0., -1.);
+        for incidence in [0_f64, 0.5, 1.2] {
+            let dir = Vec3::new(incidence.sin(), 0., incidence.cos());
+            let mirror = dir.sub(&normal.mulf(dir.dot(&normal) * 2.));
+            let mut last_spread = 0.;
+            for roughness in [0, 80, 150, 220] {
+                let samples = 20000;
+                let (mut energy, mut spread) = (0., 0.);
+                for _ in 0..samples {
+                    if let Some((r, weight)) = glossy_reflection(&dir, &normal, roughness, &mut rng)
+                    {
+                        assert!(r.dot(&normal) > 0.);
+                        energy += weight / samples as f64;
+                        spread += (1. - r.dot(&mirror)) / samples as f64;
+                    }
+                }
+                // shadowing and masking only ever remove light
+                assert!(energy <= 1. + 1e-9 && energy > 0.3, "{roughness}: {energy}");
+                assert!(spread >= last_spread);
+                last_spread = spread;
+            }
+        }
+    }
+
+    #[test]
+    fn fresnel_follows_snell() {
+        let normal = Vec3::new(0., 0., -1.);
+        let (f, t) = fresnel_refract(&Vec3::new(0., 0., 1.), &normal, 1., 1.5);
+        assert!((f - 0.04).abs() < 1e-12);
+        assert_close(&t.unwrap(), &Vec3::new(0., 0., 1.));
+        for angle in [0.2_f64, 0.7, 1.3] {
+            let dir = Vec3::new(angle.sin(), 0., angle.cos());
+            let (f, t) = fresnel_refract(&dir, &normal, 1., 1.5);
+            let t = t.unwrap();
+            assert!(f > 0.04 && f < 1.);
+            assert!((angle.sin() - 1.5 * t.x).abs() < 1e-9);
+            // the way back out takes the same path
+            let (back, out) = fresnel_refract(&t, &normal, 1.5, 1.);
+            assert!((back - f).abs() < 1e-9);
+            assert_close(&out.unwrap(), &dir);
+        }
+        // beyond the critical angle of asin(1 / 1.5) everything is reflected
+        let dir = Vec3::new(0.8_f64.sin(), 0., 0.8_f64.cos());
+        assert_eq!(fresnel_refract(&dir, &normal, 1.5, 1.), (1., None));
+    }
+
+    #[test]
+    fn glass_absorbs_and_transmits() {
+        // a light wall behind a two voxel thick pane of tinted glass
+        let scene = Scene::parse(
+            "bounds -16 -16 -16 32\n\
+             box -8 -8 0 8 8 1 glass 128 200 255 1.5 0.5\n\
+             box -8 -8 4 8 8 4 emission 255 255 255 0\n",
+        )
+        .unwrap();
+        let mut settings = settings(Integrator::Direct);
+        settings.bounces = 3;
+        let (origin, dir) = (Vec3::new(0.5, 0.5, -5.), Vec3::new(0., 0., 1.));
+        let tint = color_to_linear(&[128, 200, 255]);
+        let expected = tint.mulf(0.96 * 0.96);
+        // the rays are pushed a bit into the glass, which makes the way through longer
+        let direct = direct_color(&origin, &dir, &scene, &settings, &mut Rng::new(0));
+        assert!(direct.sub(&expected).len() < 1e-3, "{direct:?}");
+
+        let mut rng = Rng::new(3);
+        let samples = 20000;
+        let mut path = Vec3::new(0., 0., 0.);
+        for _ in 0..samples {
+            let sample = path_color(&origin, &dir, &scene, &settings, &mut rng);
+            path = path.add(&sample.mulf(1. / samples as f64));
+        }
+        assert!(path.sub(&expected).len() < 0.02, "{path:?}");
+
+        // shadow rays are only absorbed
+        let open =
+            Scene::parse("bounds -16 -16 -16 32\nvoxel 0 0 0 emission 255 255 255 0\n").unwrap();
+        let shaded = Scene::parse(
+            "bounds -16 -16 -16 32\n\
+             voxel 0 0 0 emission 255 255 255 0\n\
+             box -4 -4 2 4 4 3 glass 128 200 255 1.5 0.5\n",
+        )
+        .unwrap();
+        let (position, up) = (Vec3::new(0.5, 0.5, 6.), Vec3::new(0., 0., -1.));
+        let light = |scene: &Scene| {
+            area_light(
+                &position,
+                &up,
+                &Vec3::newi(0, 0, 0),
+                scene,
+                1,
+                &mut Rng::new(0),
+            )
+        };
+        assert!(light(&shaded).sub(&light(&open).mul(&tint)).len() < 1e-5);
+    }
+
+    #[test]
+    fn clear_glass_does_not_count_lights_twice() {
+        // shadow rays see the lamp through the pane, so diffuse bounces which refract
+        // through it must not add the lamp again
+        let open = "bounds -16 -16 -16 32\n\
+             box -8 -8 0 8 8 0 rough 200 200 200 255\n\
+             voxel 0 0 -4 emission 255 255 255 40\n";
+        let paned = format!("{open}box -3 -3 -2 3 3 -2 glass 255 255 255 1 0\n");
+        let mut settings = settings(Integrator::Path);
+        settings.bounces = 4;
+        let (origin, dir) = (Vec3::new(2.5, 0.5, -0.5), Vec3::new(0., 0., 1.));
+        let estimate = |source: &str| {
+            let scene = Scene::parse(source).unwrap();
+            let mut rng = Rng::new(5);
+            let samples = 2000;
+            (0..samples)
+                .map(|_| path_color(&origin, &dir, &scene, &settings, &mut rng).x)
+                .sum::<f64>()
+                / samples as f64
+        };
+        let (open, paned) = (estimate(open), estimate(&paned));
+        assert!(open > 0.);
+        assert!((paned - open).abs() < 0.01 * open, "{paned} != {open}");
+    }
+
+    #[test]
+    fn sky_lights_open_floor() {
+        let scene = Scene::parse(
+            "bounds -16 -16 -16 32\n\
+             sky color 255 255 255 2\n\
+             box -16 -16 0 15 15 0 rough 255 128 0 255\n",
+        )
+        .unwrap();
+        let origin = Vec3::new(0.5, 0.5, -5.);
+        let mut rng = Rng::new(1);
+        for integrator in [Integrator::Direct, Integrator::Path] {
+            let settings = settings(integrator);
+            let color = |dir: &Vec3, rng: &mut Rng| match integrator {
+                Integrator::Direct => direct_color(&origin, dir, &scene, &settings, rng),
+                Integrator::Path => path_color(&origin, dir, &scene, &settings, rng),
+            };
+            // nothing blocks the sky above the floor, so it reflects the sky times its albedo
+            let floor = color(&Vec3::new(0., 0.3, 1.).normalized(), &mut rng);
+            assert_close(&floor, &Vec3::new(2., 2. * 0.2158605001138992, 0.));
+            let background = color(&Vec3::new(0., 0.3, -1.).normalized(), &mut rng);
+            assert_close(&background, &Vec3::new(2., 2., 2.));
+        }
+    }
+
+    #[test]
+    fn analytic_lights_cast_shadows() {
+        let open = Scene::parse("bounds -16 -16 -16 32\n").unwrap();
+        let blocked =
+            Scene::parse("bounds -16 -16 -16 32\nbox -1 -1 -3 1 1 -3 rough 1 1 1 255\n").unwrap();
+        let (position, up) = (Vec3::new(0., 0., 0.), Vec3::new(0., 0., -1.));
+        let light = |scene: &Scene, light: &Light, samples: usize| {
+            analytic_light(&position, &up, light, scene, samples, &mut Rng::new(0))
+        };
+
+        let point = Light::Point {
+            position: Vec3::new(3., 0., -4.),
+            intensity: Vec3::new(50., 50., 50.),
+        };
+        // intensity * cos / r^2 = 50 * 0.8 / 25
+        assert_close(&light(&open, &point, 1), &Vec3::new(1.6, 1.6, 1.6));
+        let above = Light::Point {
+            position: Vec3::new(0., 0., -6.),
+            intensity: Vec3::new(50., 50., 50.),
+        };
+        assert_close(&light(&blocked, &above, 1), &Vec3::new(0., 0., 0.));
+        // the blocker is behind the light
+        let below_blocker = Light::Point {
+            position: Vec3::new(0., 0., -1.),
+            intensity: Vec3::new(1., 1., 1.),
+        };
+        assert_close(&light(&blocked, &below_blocker, 1), &Vec3::new(1., 1., 1.));
+
+        // the blocker covers the center of the sun but not all of it
+        let sun = Light::Sun {
+            direction: Vec3::new(0.84, 0., -1.).normalized(),
+            radius: 0.3,
+            irradiance: Vec3::new(1., 1., 1.),
+        };
+        assert_close(&light(&blocked, &sun, 1), &Vec3::new(0., 0., 0.));
+        let penumbra = light(&blocked, &sun, 64).x;
+        let unblocked = light(&open, &sun, 64).x;
+        assert!(
+            penumbra > unblocked * 0.1 && penumbra < unblocked * 0.9,
+            "{penumbra}"
+        );
+        assert!(
+            (unblocked - 0.84_f64.atan().cos()).abs() < 0.03,
+            "{unblocked}"
+        );
+    }
+
+    #[test]
+    fn light_selection_is_unbiased() {
+        let mut source =
+            "bounds -16 -16 -16 32\nbox -16 -16 0 15 15 0 rough 200 200 200 255\n".to_string();
+        for (i, (x, y)) in [
+            (-6, -6),
+            (-2, 5),
+            (3, -4),
+            (7, 7),
+            (9, -9),
+            (-10, 2),
+            (0, 12),
+        ]
+        .iter()
+        .enumerate()
+        {
+            source += &format!("voxel {x} {y} -2 emission 255 255 255 {}\n", 40 + 10 * i);
+        }
+        // one light behind the floor, which can't be seen
+        source += "voxel 0 0 3 emission 255 255 255 120\n";
+        let scene = Scene::parse(&source).unwrap();
+        let mut settings = settings(Integrator::Direct);
+        let (position, up) = (Vec3::new(0.5, 0.5, -1e-3), Vec3::new(0., 0., -1.));
+        let exact = direct_light(&position, &up, &scene, &settings, &mut Rng::new(0));
+
+        // enough samples for every light gives the same result
+        settings.light_samples = 8;
+        let selected = select_lights(&position, &up, &scene, 7, &mut Rng::new(0));
+        assert_eq!(selected.len(), 7);
+        assert!(selected.iter().all(|&(_, weight)| weight == 1.));
+        let all = direct_light(&position, &up, &scene, &settings, &mut Rng::new(0));
+        assert!(all.sub(&exact).len() < 1e-9);
+
+        settings.light_samples = 2;
+        let mut rng = Rng::new(3);
+        let runs = 4000;
+        let mut mean = Vec3::new(0., 0., 0.);
+        for _ in 0..runs {
+            let light = direct_light(&position, &up, &scene, &settings, &mut rng);
+            mean = mean.add(&light.mulf(1. / runs as f64));
+        }
+        assert!(
+            (mean.x - exact.x).abs() < 0.02 * exact.x,
+            "{mean:?} != {exact:?}"
+        );
+    }
+
+    #[test]
+    fn culling_only_drops_faint_light() {
+        // weak lamps spread over the floor, most of them culled far away
+        let mut source = TEST_SCENE.to_string();
+        for (x, y) in [(-7, -7), (-7, 6), (6, -7), (6, 6), (0, 6), (-7, 0)] {
+            source += &format!("voxel {x} {y} -1 emission 255 255 255 20\n");
+        }
+        let mut scene = Scene::parse(&source).unwrap();
+        scene
+            .lights
+            .set_limits(LightLimits::new(0.05, 0.2).unwrap());
+        let settings = settings(Integrator::Direct);
+        let culled = render_image(&scene, &settings, 1);
+        let report = compare_culling(&mut scene, &settings, 1, &culled);
+        assert_eq!(scene.lights.limits(), LightLimits::new(0.05, 0.2).unwrap());
+        assert!(report.max_error > 0.);
+        assert!(report.energy < 1. && report.energy > 0.9, "{report}");
+
+        scene.lights.set_limits(LightLimits::BRUTE_FORCE);
+        let brute_force = render_image(&scene, &settings, 1);
+        let (x, y) = report.max_error_at;
+        let i = (y * settings.width + x) as usize;
+        let d = brute_force[i].sub(&culled[i]);
+        assert_eq!(d.x.abs().max(d.y.abs()).max(d.z.abs()), report.max_error);
+        // culled lights only ever take light away
+        assert!(culled
+            .iter()
+            .zip(&brute_force)
+            .all(|(c, b)| c.x <= b.x + 1e-9 && c.y <= b.y + 1e-9 && c.z <= b.z + 1e-9));
+        let same = CullingReport::compare(&brute_force, &brute_force, &settings);
+        assert_eq!((same.max_error, same.max_display_error), (0., 0));
+        assert_eq!(same.energy, 1.);
+    }
+
+    #[test]
+    fn aovs_describe_the_first_hit() {
+        // a floor and a lamp above it seen from straight above, one voxel per pixel
+        let scene = Scene::parse(
+            "bounds -16 -16 -16 32\n\
+             camera 0 0 -8 0 0 0\n\
+             camera_up 0 1 0\n\
+             orthographic 8\n\
+             box -16 -16 0 15 15 0 rough 200 100 50 255\n\
+             voxel 0 0 -2 emission 255 230 200 90\n",
+        )
+        .unwrap();
+        let floor = VoxelMaterial::rough([200, 100, 50], 255);
+        let lamp = VoxelMaterial::emissive([255, 230, 200], 90);
+        let mut settings = settings(Integrator::Direct);
+        settings.width = 8;
+        settings.height = 8;
+        settings.samples = 1;
+        let (image, passes) = render_passes(&scene, &settings, &Aov::ALL, 1);
+        assert_eq!(passes.len(), Aov::ALL.len());
+        // collecting passes doesn't change the image
+        assert!(image == render_image(&scene, &settings, 1));
+        let [depth, normal, albedo, emission, steps, material] = &passes[..] else {
+            unreachable!();
+        };
+        let gray = |v: f64| Vec3::new(v, v, v);
+        let lamp_pixels: Vec<_> = (0..64).filter(|&i| depth[i].x < 7.).collect();
+        assert_eq!(lamp_pixels.len(), 1);
+        for i in 0..64 {
+            let color = color_to_linear(if lamp_pixels.contains(&i) {
+                &[255, 230, 200]
+            } else {
+                &[200, 100, 50]
+            });
+            assert_close(&albedo[i], &color);
+            assert_close(&normal[i], &Vec3::new(0., 0., -1.));
+            assert!(steps[i].x >= 1.);
+            if lamp_pixels.contains(&i) {
+                assert_close(&depth[i], &gray(6.));
+                assert_close(&emission[i], &color.mulf(emission_strength_from_u8(90)));
+                assert_eq!(material[i], gray(lamp.id() as f64));
+            } else {
+                assert_close(&depth[i], &gray(8.));
+                assert_eq!(emission[i], gray(0.));
+                assert_eq!(material[i], gray(floor.id() as f64));
+            }
+        }
+
+        // antialiased passes blend at the edges, but IDs are never mixed
+        settings.samples = 16;
+        let (_, passes) = render_passes(&scene, &settings, &[Aov::MaterialId, Aov::Depth], 2);
+        assert!(passes[0]
+            .iter()
+            .all(|id| [floor.id(), lamp.id()].contains(&(id.x as u32))));
+        assert!(passes[1].iter().any(|d| d.x > 6. && d.x < 8.));
+        assert!(render_passes(&scene, &settings, &[], 1).1.is_empty());
+    }
+}
@@ -0,0 +1,295 @@
+//! Random numbers and direction sampling for the stochastic integrators.
+
+use std::{f64::consts::TAU, str::FromStr};
+
+use crate::math::Vec3;
+
+/// Small seedable generator (xorshift64*), every pixel gets its own stream so
+/// renders are reproducible no matter which thread renders which pixel.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// Generator seeded with `seed`, similar seeds give unrelated streams.
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on 0, splitmix spreads similar seeds apart
+        Rng(splitmix64(seed) | 1)
+    }
+
+    /// Stream for the pixel (x, y) of a render seeded with `seed`.
+    pub fn for_pixel(seed: u64, x: u32, y: u32) -> Self {
+        Rng::new(seed ^ splitmix64(((x as u64) << 32) | y as u64))
+    }
+
+    /// Next 64 random bits.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    /// Uniform float in [0, 1).
+    pub fn float(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// Two unit vectors which form an orthonormal basis together with `n`.
+// https://graphics.pixar.com/library/OrthonormalB/paper.pdf
+pub fn orthonormal_basis(n: &Vec3) -> (Vec3, Vec3) {
+    let s = if n.z >= 0. { 1. } else { -1. };
+    let a = -1. / (s + n.z);
+    let b = n.x * n.y * a;
+    (
+        Vec3::new(1. + s * n.x * n.x * a, s * b, -s * n.x),
+        Vec3::new(b, s + n.y * n.y * a, -n.y),
+    )
+}
+
+/// Direction on the hemisphere around `n` with a pdf of cos(theta) / PI.
+pub fn cosine_hemisphere(n: &Vec3, rng: &mut Rng) -> Vec3 {
+    let r = rng.float().sqrt();
+    let angle = rng.float() * TAU;
+    let (x, y) = (r * angle.cos(), r * angle.sin());
+    let z = (1. - x * x - y * y).max(0.).sqrt();
+    let (b1, b2) = orthonormal_basis(n);
+    b1.mulf(x).add(&b2.mulf(y)).add(&n.mulf(z))
+}
+
+/// Width of the GGX microfacet distribution for a roughness in [0, 255].
+pub fn ggx_alpha(roughness: u8) -> f64 {
+    (roughness as f64 / 255.).powi(2)
+}
+
+/// Microfacet normal around `n` sampled proportional to D(h) * cos(theta_h) of the GGX
+/// distribution with width `alpha`.
+pub fn ggx_half_vector(n: &Vec3, alpha: f64, rng: &mut Rng) -> Vec3 {
+    let u = rng.float();
+    let angle = rng.float() * TAU;
+    let tan2 = alpha * alpha * u / (1. - u);
+    let cos = 1. / (1. + tan2).sqrt();
+    let sin = (1. - cos * cos).max(0.).sqrt();
+    let (b1, b2) = orthonormal_basis(n);
+    b1.mulf(sin * angle.cos())
+        .add(&b2.mulf(sin * angle.sin()))
+        .add(&n.mulf(cos))
+}
+
+/// Smith masking term of GGX for a direction with cosine `n_dot` to the normal.
+pub fn ggx_g1(n_dot: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    2. * n_dot / (n_dot + (a2 + (1. - a2) * n_dot * n_dot).sqrt())
+}
+
+/// Position of sample `i` out of `n` inside the unit square. The square is split
+/// into rows of ceil(sqrt(n)) cells, the last row takes the remaining samples and is
+/// lower so that every cell has an area of 1 / n. Each sample is jittered inside its
+/// own cell.
+pub fn stratified(i: usize, n: usize, rng: &mut Rng) -> (f64, f64) {
+    let k = (n as f64).sqrt().ceil() as usize;
+    let i = i % n;
+    let start = i / k * k;
+    let row = k.min(n - start);
+    (
+        ((i - start) as f64 + rng.float()) / row as f64,
+        (start as f64 + rng.float() * row as f64) / n as f64,
+    )
+}
+
+/// Reconstruction filter weighting the samples of a pixel by their offset from
+/// the pixel center.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixelFilter {
+    /// Equal weights over the pixel.
+    Box,
+    /// Weights falling off linearly until the centers of the neighbouring pixels.
+    Tent,
+    /// Gaussian weights reaching 1.5 pixels from the center.
+    Gaussian,
+}
+
+impl PixelFilter {
+    /// Samples are spread over the square of +-radius pixels around the center.
+    pub fn radius(&self) -> f64 {
+        match self {
+            PixelFilter::Box => 0.5,
+            PixelFilter::Tent => 1.,
+            PixelFilter::Gaussian => 1.5,
+        }
+    }
+
+    /// Weight of a sample at the offset (dx, dy) from the pixel center, in pixels.
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        match self {
+            PixelFilter::Box => 1.,
+            PixelFilter::Tent => (1. - dx.abs()).max(0.) * (1. - dy.abs()).max(0.),
+            PixelFilter::Gaussian => {
+                // shifted down so the weight reaches zero at the radius
+                let r = self.radius();
+                let g = |d: f64| ((-2. * d * d).exp() - (-2. * r * r).exp()).max(0.);
+                g(dx) * g(dy)
+            }
+        }
+    }
+}
+
+impl FromStr for PixelFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(PixelFilter::Box),
+            "tent" => Ok(PixelFilter::Tent),
+            "gaussian" => Ok(PixelFilter::Gaussian),
+            _ => Err(format!(
+                "unknown filter `{s}`, expected box, tent or gaussian"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn orthonormal_basis_is_orthonormal() {
+        let mut rng = Rng::new(7);
+        for _ in 0..100 {
+            let n = unit_vec(&mut rng);
+            let (b1, b2) = orthonormal_basis(&n);
+            for (a, b) in [(&n, &b1), (&n, &b2), (&b1, &b2)] {
+                assert!(a.dot(b).abs() < 1e-9);
+            }
+            assert!((b1.len() - 1.).abs() < 1e-9 && (b2.len() - 1.).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn cosine_hemisphere_is_cosine_distributed() {
+        let mut test_rng = Rng::new(3);
+        let mut rng = Rng::new(1);
+        for _ in 0..20 {
+            let n = unit_vec(&mut test_rng);
+            let samples = 20000;
+            let mut mean_cos = 0.;
+            for _ in 0..samples {
+                let d = cosine_hemisphere(&n, &mut rng);
+                assert!((d.len() - 1.).abs() < 1e-9);
+                assert!(d.dot(&n) >= 0.);
+                mean_cos += d.dot(&n) / samples as f64;
+            }
+            // E[cos] = integral of cos^2 / PI over the hemisphere
+            assert!((mean_cos - 2. / 3.).abs() < 0.01, "{mean_cos}");
+        }
+    }
+
+    #[test]
+    fn pixel_streams_are_reproducible() {
+        let a: Vec<u64> = (0..4).map(|_| Rng::for_pixel(5, 3, 4).next_u64()).collect();
+        assert!(a.iter().all(|&v| v == a[0]));
+        assert_ne!(
+            Rng::for_pixel(5, 3, 4).next_u64(),
+            Rng::for_pixel(5, 4, 3).next_u64()
+        );
+        assert_ne!(
+            Rng::for_pixel(5, 3, 4).next_u64(),
+            Rng::for_pixel(6, 3, 4).next_u64()
+        );
+    }
+
+    #[test]
+    fn stratified_samples_fill_every_cell() {
+        let mut rng = Rng::new(9);
+        let mut cells = [0; 16];
+        for i in 0..16 {
+            let (x, y) = stratified(i, 16, &mut rng);
+            assert!((0. ..1.).contains(&x) && (0. ..1.).contains(&y));
+            cells[(y * 4.) as usize * 4 + (x * 4.) as usize] += 1;
+        }
+        assert!(cells.iter().all(|&c| c == 1));
+    }
+
+    #[test]
+    fn stratified_samples_cover_the_square_evenly() {
+        let mut rng = Rng::new(2);
+        for n in [2, 3, 5, 8] {
+            let runs = 20000;
+            let (mut mean_x, mut mean_y) = (0., 0.);
+            let mut cells = [0; 16];
+            for _ in 0..runs {
+                for i in 0..n {
+                    let (x, y) = stratified(i, n, &mut rng);
+                    assert!((0. ..1.).contains(&x) && (0. ..1.).contains(&y));
+                    mean_x += x / (runs * n) as f64;
+                    mean_y += y / (runs * n) as f64;
+                    cells[(y * 4.) as usize * 4 + (x * 4.) as usize] += 1;
+                }
+            }
+            assert!((mean_x - 0.5).abs() < 0.005, "{n}: {mean_x}");
+            assert!((mean_y - 0.5).abs() < 0.005, "{n}: {mean_y}");
+            let expected = (runs * n) as f64 / 16.;
+            for c in cells {
+                assert!(
+                    (c as f64 - expected).abs() < 0.05 * expected,
+                    "{n}: {cells:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn filters_peak_at_center_and_vanish_at_radius() {
+        for filter in [PixelFilter::Box, PixelFilter::Tent, PixelFilter::Gaussian] {
+            let r = filter.radius();
+            let center = filter.weight(0., 0.);
+            assert!(center > 0.);
+            assert!(filter.weight(r * 0.5, -r * 0.5) <= center);
+            if filter != PixelFilter::Box {
+                assert!(filter.weight(r, 0.) < 1e-12);
+                assert!(filter.weight(0., -r) < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn ggx_half_vectors_widen_with_alpha() {
+        let mut rng = Rng::new(4);
+        let n = Vec3::new(0., 0., -1.);
+        let mut last_mean = 0.;
+        for alpha in [0.01, 0.1, 0.3, 0.6, 1.] {
+            let samples = 5000;
+            let mut mean_angle = 0.;
+            for _ in 0..samples {
+                let h = ggx_half_vector(&n, alpha, &mut rng);
+                assert!((h.len() - 1.).abs() < 1e-9);
+                assert!(h.dot(&n) > 0.);
+                mean_angle += h.dot(&n).min(1.).acos() / samples as f64;
+            }
+            assert!(mean_angle > last_mean, "{alpha}: {mean_angle}");
+            last_mean = mean_angle;
+        }
+        // a smooth surface behaves like a mirror
+        assert!(ggx_half_vector(&n, 0., &mut rng) == n);
+    }
+
+    #[test]
+    fn ggx_g1_is_a_fraction() {
+        for alpha in [0., 0.2, 1.] {
+            assert!((ggx_g1(1., alpha) - 1.).abs() < 1e-12);
+            for i in 1..10 {
+                let g = ggx_g1(i as f64 / 10., alpha);
+                assert!(g > 0. && g <= 1.);
+            }
+        }
+    }
+}
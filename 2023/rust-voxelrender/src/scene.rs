@@ -0,0 +1,793 @@
+//! Text scene description loaded by the renderer at runtime.
+//!
+//! A scene file is read line by line, `#` starts a comment and empty lines are
+//! ignored. Every other line is a directive followed by whitespace separated
+//! arguments:
+//!
+//! ```text
+//! bounds <x> <y> <z> <size>                  whole numbers, size must be a power of two
+//! camera <x> <y> <z> <tx> <ty> <tz>          camera position and the point it looks at
+//! camera_up <x> <y> <z>                      camera up vector, defaults to 0 0 -1 (z is down)
+//! fov <degrees>                              vertical field of view of the perspective camera
+//! orthographic <height>                      orthographic camera showing <height> world units
+//! material <name> rough <r> <g> <b> <roughness>
+//! material <name> emission <r> <g> <b> <emission>
+//! material <name> glass <r> <g> <b> <ior> <density>
+//! sky color <r> <g> <b> <strength>          constant light from every direction
+//! sky gradient <zenith rgb> <horizon rgb> <ground rgb> <strength>
+//! sky sun <dx> <dy> <dz> <degrees> <r> <g> <b> <strength>  sun disc in the gradient sky
+//! sky image <path> <strength>                equirectangular image, relative to the scene file
+//! light sun <dx> <dy> <dz> <degrees> <r> <g> <b> <strength>
+//! light point <x> <y> <z> <r> <g> <b> <strength>
+//! light spot <x> <y> <z> <dx> <dy> <dz> <inner> <outer> <r> <g> <b> <strength>
+//! light_limits <useful> <not_useful>         brightness limits of the lighting tree
+//! voxel <x> <y> <z> <material>               a single voxel
+//! box <x0> <y0> <z0> <x1> <y1> <z1> <material>  all voxels between both corners (inclusive)
+//! ```
+//!
+//! `<material>` is either the name of a previously defined material or an
+//! inline definition like `rough 200 200 200 150`. Emissive voxels are added to
+//! the lighting tree automatically. `bounds` has to come before the first voxel.
+//! Rays leaving the octree see the `sky`, which is black unless it is set. The sun
+//! of `sky sun` has the given angular radius and needs a `sky gradient` before it.
+//!
+//! `light` adds a light which is not made of voxels and is invisible itself. The sun
+//! shines from the direction `<dx> <dy> <dz>` with the angular radius `<degrees>`, which
+//! has to be above 0 and below 90 like for `sky sun`. Its strength is the irradiance on a surface facing it. Point and spot lights have their
+//! strength as intensity, a spot shines along `<dx> <dy> <dz>` and fades out between
+//! the `<inner>` and `<outer>` cone angles in degrees.
+//!
+//! `light_limits` sets the [`LightLimits`] deciding which emissive voxels are skipped
+//! in which regions, `light_limits 0 0` keeps every light everywhere.
+
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    camera::{Camera, Projection},
+    environment::{Environment, SkySun},
+    lighting::{Light, LightLimits, LightingTree},
+    material::{color_to_linear, MatTree, VoxelMaterial},
+    math::{Cube, Vec3},
+    octree::Octree,
+};
+
+const DEFAULT_BOUNDS: Cube = Cube {
+    fpos: Vec3 {
+        x: -64.,
+        y: -64.,
+        z: -64.,
+    },
+    size: 128.,
+};
+
+/// Error while loading a scene, parse errors carry their 1-based line number.
+#[derive(Debug)]
+pub enum SceneError {
+    /// The scene file couldn't be read.
+    Io(std::io::Error),
+    /// A line of the scene is malformed.
+    Parse {
+        /// 1-based line number.
+        line: usize,
+        /// What is wrong with the line.
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "failed to read scene: {e}"),
+            SceneError::Parse { line, message } => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// Voxels, lights, camera and surrounding environment of a scene.
+pub struct Scene {
+    /// Voxels and their materials.
+    pub tree: MatTree,
+    /// Lights, the emissive voxels among them are kept in sync with `tree` by the methods
+    /// of the scene.
+    pub lights: LightingTree,
+    /// Camera the scene is rendered through.
+    pub camera: Camera,
+    /// What rays leaving the octree see.
+    pub environment: Environment,
+}
+
+impl Scene {
+    /// Empty scene covering `bounds` with the default camera and a black environment.
+    pub fn new(bounds: Cube) -> Self {
+        Scene {
+            tree: Octree::new(bounds),
+            lights: LightingTree::new(bounds),
+            camera: default_camera(),
+            environment: Environment::default(),
+        }
+    }
+
+    /// Loads a scene file, files it references are relative to its directory.
+    pub fn load(path: &Path) -> Result<Self, SceneError> {
+        let source = fs::read_to_string(path).map_err(SceneError::Io)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Self::parse_in(&source, dir)
+    }
+
+    /// Parses a scene, files it references are relative to the working directory.
+    pub fn parse(source: &str) -> Result<Self, SceneError> {
+        Self::parse_in(source, Path::new(""))
+    }
+
+    fn parse_in(source: &str, dir: &Path) -> Result<Self, SceneError> {
+        let mut parser = Parser {
+            scene: None,
+            materials: HashMap::new(),
+            camera: default_camera(),
+            environment: Environment::default(),
+            lights: vec![],
+            light_limits: None,
+            dir: dir.to_path_buf(),
+        };
+        for (i, line) in source.lines().enumerate() {
+            let line_nr = i + 1;
+            let line = line.split('#').next().unwrap_or("");
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens.is_empty() {
+                continue;
+            }
+            parser
+                .directive(&tokens)
+                .map_err(|message| SceneError::Parse {
+                    line: line_nr,
+                    message,
+                })?;
+        }
+
+        let mut scene = parser.scene.unwrap_or_else(|| Scene::new(DEFAULT_BOUNDS));
+        scene.camera = parser.camera;
+        scene.environment = parser.environment;
+        for light in parser.lights {
+            scene.add_light(light);
+        }
+        if let Some(limits) = parser.light_limits {
+            scene.lights.set_limits(limits);
+        }
+        Ok(scene)
+    }
+
+    /// Sets a single voxel and registers it as light if it is emissive. A light which
+    /// was there before is replaced. Positions outside of the bounds are ignored.
+    pub fn insert(&mut self, position: Vec3, material: VoxelMaterial) {
+        if !self.tree.bounds.containsf(&position) {
+            return;
+        }
+        self.tree.insert(position, material);
+        match material {
+            VoxelMaterial::Emission { emission, .. } => self.lights.insert(position, emission),
+            _ => {
+                self.lights.remove(position);
+            }
+        }
+    }
+
+    /// Empties a single voxel, together with its light.
+    pub fn remove(&mut self, position: Vec3) {
+        self.tree.remove(position);
+        self.lights.remove(position);
+    }
+
+    /// Adds a light which is not made of voxels.
+    pub fn add_light(&mut self, light: Light) {
+        self.lights.add(light);
+    }
+
+    /// Fills every voxel from `min` to `max`, both inclusive. Voxels outside of the
+    /// bounds are left out.
+    pub fn fill_box(&mut self, min: Vec3, max: Vec3, material: VoxelMaterial) {
+        self.tree.fill_box(min, max, material);
+        let end = max.add(&Vec3::new(1., 1., 1.));
+        let VoxelMaterial::Emission { emission, .. } = material else {
+            self.lights.remove_box(min, max);
+            return;
+        };
+        let Cube { fpos: lo, size } = self.tree.bounds;
+        let hi = lo.add(&Vec3::new(size, size, size));
+        for x in min.x.max(lo.x) as i64..end.x.min(hi.x) as i64 {
+            for y in min.y.max(lo.y) as i64..end.y.min(hi.y) as i64 {
+                for z in min.z.max(lo.z) as i64..end.z.min(hi.z) as i64 {
+                    self.lights.insert(Vec3::newi(x, y, z), emission);
+                }
+            }
+        }
+    }
+
+    /// Empties every voxel from `min` to `max`, both inclusive.
+    pub fn clear_box(&mut self, min: Vec3, max: Vec3) {
+        self.tree.clear_box(min, max);
+        self.lights.remove_box(min, max);
+    }
+}
+
+struct Parser {
+    scene: Option<Scene>,
+    materials: HashMap<String, VoxelMaterial>,
+    camera: Camera,
+    environment: Environment,
+    // lights which are not made of voxels, added once the bounds are known
+    lights: Vec<Light>,
+    // applied once all lights are known, so they are sorted only once
+    light_limits: Option<LightLimits>,
+    // directory of the scene file
+    dir: PathBuf,
+}
+
+impl Parser {
+    fn directive(&mut self, tokens: &[&str]) -> Result<(), String> {
+        let args = &tokens[1..];
+        match tokens[0] {
+            "bounds" => {
+                if self.scene.is_some() {
+                    return Err("bounds must be set once, before the first voxel".to_string());
+                }
+                let [x, y, z, size] = parse_n::<f64, 4>(args, "bounds")?;
+                if [x, y, z].iter().any(|c| c.fract() != 0.) {
+                    return Err(format!("bounds corner {x} {y} {z} must be whole numbers"));
+                }
+                if size < 1. || size.log2().fract() != 0. {
+                    return Err(format!("bounds size {size} is not a power of two"));
+                }
+                self.scene = Some(Scene::new(Cube::new(x, y, z, size)));
+            }
+            "camera" => {
+                let [x, y, z, tx, ty, tz] = parse_n::<f64, 6>(args, "camera")?;
+                let (position, target) = (Vec3::new(x, y, z), Vec3::new(tx, ty, tz));
+                if position == target {
+                    return Err("camera position and target must differ".to_string());
+                }
+                self.camera.position = position;
+                self.camera.target = target;
+            }
+            "camera_up" => {
+                let [x, y, z] = parse_n::<f64, 3>(args, "camera_up")?;
+                if x == 0. && y == 0. && z == 0. {
+                    return Err("camera_up must not be zero".to_string());
+                }
+                self.camera.up = Vec3::new(x, y, z);
+            }
+            "fov" => {
+                let [fov] = parse_n::<f64, 1>(args, "fov")?;
+                if !(fov > 0. && fov < 180.) {
+                    return Err(format!("fov {fov} must be between 0 and 180 degrees"));
+                }
+                self.camera.projection = Projection::Perspective { fov };
+            }
+            "orthographic" => {
+                let [height] = parse_n::<f64, 1>(args, "orthographic")?;
+                if height <= 0. {
+                    return Err(format!("orthographic height {height} must be positive"));
+                }
+                self.camera.projection = Projection::Orthographic { height };
+            }
+            "material" => {
+                let Some((name, spec)) = args.split_first() else {
+                    return Err("material needs a name and a definition".to_string());
+                };
+                let material = parse_material_def(spec)?;
+                self.materials.insert(name.to_string(), material);
+            }
+            "sky" => self.sky(args)?,
+            "light_limits" => {
+                let [useful, not_useful] = parse_n::<f64, 2>(args, "light_limits")?;
+                self.light_limits = Some(LightLimits::new(useful, not_useful)?);
+            }
+            "light" => {
+                let light = parse_light(args)?;
+                self.lights.push(light);
+            }
+            "voxel" => {
+                if args.len() < 4 {
+                    return Err(format!(
+                        "voxel expects <x> <y> <z> <material>, got {args:?}"
+                    ));
+                }
+                let [x, y, z] = parse_n::<i64, 3>(&args[..3], "voxel")?;
+                let material = self.material(&args[3..])?;
+                let position = Vec3::newi(x, y, z);
+                let scene = self.scene();
+                if !scene.tree.bounds.containsf(&position) {
+                    return Err(format!("voxel {x} {y} {z} is outside of the scene bounds"));
+                }
+                scene.insert(position, material);
+            }
+            "box" => {
+                if args.len() < 7 {
+                    return Err(format!(
+                        "box expects <x0> <y0> <z0> <x1> <y1> <z1> <material>, got {args:?}"
+                    ));
+                }
+                let [x0, y0, z0, x1, y1, z1] = parse_n::<i64, 6>(&args[..6], "box")?;
+                let material = self.material(&args[6..])?;
+                let (x0, x1) = (x0.min(x1), x0.max(x1));
+                let (y0, y1) = (y0.min(y1), y0.max(y1));
+                let (z0, z1) = (z0.min(z1), z0.max(z1));
+                let scene = self.scene();
+                let bounds = scene.tree.bounds;
+                if !bounds.containsf(&Vec3::newi(x0, y0, z0))
+                    || !bounds.containsf(&Vec3::newi(x1, y1, z1))
+                {
+                    return Err("box is outside of the scene bounds".to_string());
+                }
+                scene.fill_box(Vec3::newi(x0, y0, z0), Vec3::newi(x1, y1, z1), material);
+            }
+            other => return Err(format!("unknown directive `{other}`")),
+        }
+        Ok(())
+    }
+
+    fn sky(&mut self, args: &[&str]) -> Result<(), String> {
+        let Some((kind, args)) = args.split_first() else {
+            return Err("sky needs a type: color, gradient, sun or image".to_string());
+        };
+        match *kind {
+            "color" => {
+                let [r, g, b, strength] = parse_n::<f64, 4>(args, "sky color")?;
+                self.environment = Environment::Constant(scaled_color(r, g, b, strength)?);
+            }
+            "gradient" => {
+                let [zr, zg, zb, hr, hg, hb, gr, gg, gb, strength] =
+                    parse_n::<f64, 10>(args, "sky gradient")?;
+                self.environment = Environment::Sky {
+                    zenith: scaled_color(zr, zg, zb, strength)?,
+                    horizon: scaled_color(hr, hg, hb, strength)?,
+                    ground: scaled_color(gr, gg, gb, strength)?,
+                    sun: None,
+                };
+            }
+            "sun" => {
+                let [dx, dy, dz, angle, r, g, b, strength] = parse_n::<f64, 8>(args, "sky sun")?;
+                let direction = direction(dx, dy, dz)?;
+                let radius = sun_radius(angle)?;
+                let Environment::Sky { sun, .. } = &mut self.environment else {
+                    return Err("sky sun needs a sky gradient before it".to_string());
+                };
+                *sun = Some(SkySun {
+                    direction,
+                    radius,
+                    radiance: scaled_color(r, g, b, strength)?,
+                });
+            }
+            "image" => {
+                let [path, strength] = args else {
+                    return Err(format!("sky image expects <path> <strength>, got {args:?}"));
+                };
+                let [strength] = parse_n::<f64, 1>(&[strength], "sky image")?;
+                if strength.is_nan() || strength < 0. {
+                    return Err(format!("strength {strength} must not be negative"));
+                }
+                let path = self.dir.join(path);
+                self.environment = Environment::load_image(&path, strength)
+                    .map_err(|e| format!("failed to load `{}`: {e}", path.display()))?;
+            }
+            other => return Err(format!("unknown sky type `{other}`")),
+        }
+        Ok(())
+    }
+
+    fn scene(&mut self) -> &mut Scene {
+        self.scene.get_or_insert_with(|| Scene::new(DEFAULT_BOUNDS))
+    }
+
+    fn material(&self, spec: &[&str]) -> Result<VoxelMaterial, String> {
+        if let [name] = spec {
+            return self
+                .materials
+                .get(*name)
+                .copied()
+                .ok_or_else(|| format!("unknown material `{name}`"));
+        }
+        parse_material_def(spec)
+    }
+}
+
+// The view of the original hardcoded camera: yaw 45 degrees, pitched down by PI / 7.
+fn default_camera() -> Camera {
+    Camera::look_at(
+        Vec3::new(-4., -4., -4.),
+        Vec3::new(2.370833, 2.370833, 0.338837),
+    )
+}
+
+fn parse_light(args: &[&str]) -> Result<Light, String> {
+    let Some((kind, args)) = args.split_first() else {
+        return Err("light needs a type: sun, point or spot".to_string());
+    };
+    match *kind {
+        "sun" => {
+            let [dx, dy, dz, angle, r, g, b, strength] = parse_n::<f64, 8>(args, "light sun")?;
+            Ok(Light::Sun {
+                direction: direction(dx, dy, dz)?,
+                radius: sun_radius(angle)?,
+                irradiance: scaled_color(r, g, b, strength)?,
+            })
+        }
+        "point" => {
+            let [x, y, z, r, g, b, strength] = parse_n::<f64, 7>(args, "light point")?;
+            Ok(Light::Point {
+                position: Vec3::new(x, y, z),
+                intensity: scaled_color(r, g, b, strength)?,
+            })
+        }
+        "spot" => {
+            let [x, y, z, dx, dy, dz, inner, outer, r, g, b, strength] =
+                parse_n::<f64, 12>(args, "light spot")?;
+            if !(inner >= 0. && inner <= outer && outer <= 180.) {
+                return Err(format!(
+                    "spot angles {inner} and {outer} must satisfy 0 <= inner <= outer <= 180"
+                ));
+            }
+            Ok(Light::Spot {
+                position: Vec3::new(x, y, z),
+                direction: direction(dx, dy, dz)?,
+                inner: inner.to_radians(),
+                outer: outer.to_radians(),
+                intensity: scaled_color(r, g, b, strength)?,
+            })
+        }
+        other => Err(format!("unknown light type `{other}`")),
+    }
+}
+
+// Angular radius in radians of a `sky sun` or `light sun` given in degrees.
+fn sun_radius(angle: f64) -> Result<f64, String> {
+    if !(angle > 0. && angle < 90.) {
+        return Err(format!(
+            "sun angle {angle} must be between 0 and 90 degrees"
+        ));
+    }
+    Ok(angle.to_radians())
+}
+
+fn direction(x: f64, y: f64, z: f64) -> Result<Vec3, String> {
+    let dir = Vec3::new(x, y, z);
+    if dir.len().is_nan() || dir.len() == 0. {
+        return Err("direction must not be zero".to_string());
+    }
+    Ok(dir.normalized())
+}
+
+// Linear color of an 8 bit sRGB color of a light or the sky scaled by `strength`.
+fn scaled_color(r: f64, g: f64, b: f64, strength: f64) -> Result<Vec3, String> {
+    let mut color = [0; 3];
+    for (c, v) in color.iter_mut().zip([r, g, b]) {
+        if !(0. ..=255.).contains(&v) || v.fract() != 0. {
+            return Err(format!("color `{v}` must be a whole number from 0 to 255"));
+        }
+        *c = v as u8;
+    }
+    if strength.is_nan() || strength < 0. {
+        return Err(format!("strength {strength} must not be negative"));
+    }
+    Ok(color_to_linear(&color).mulf(strength))
+}
+
+fn parse_material_def(spec: &[&str]) -> Result<VoxelMaterial, String> {
+    let Some((kind, args)) = spec.split_first() else {
+        return Err("missing material definition".to_string());
+    };
+    match *kind {
+        "rough" => {
+            let [r, g, b, roughness] = parse_n::<u8, 4>(args, "rough")?;
+            Ok(VoxelMaterial::rough([r, g, b], roughness))
+        }
+        "emission" => {
+            let [r, g, b, emission] = parse_n::<u8, 4>(args, "emission")?;
+            Ok(VoxelMaterial::emissive([r, g, b], emission))
+        }
+        "glass" => {
+            if args.len() != 5 {
+                return Err(format!(
+                    "glass expects <r> <g> <b> <ior> <density>, got {args:?}"
+                ));
+            }
+            let [r, g, b] = parse_n::<u8, 3>(&args[..3], "glass")?;
+            let [ior, density] = parse_n::<f64, 2>(&args[3..], "glass")?;
+            if ior.is_nan() || ior < 1. {
+                return Err(format!("glass ior {ior} must be at least 1"));
+            }
+            if density.is_nan() || density < 0. {
+                return Err(format!("glass density {density} must not be negative"));
+            }
+            Ok(VoxelMaterial::glass([r, g, b], ior, density))
+        }
+        other => Err(format!("unknown material type `{other}`")),
+    }
+}
+
+// Numbers of a directive, floats have to be finite.
+trait Number: FromStr + Copy + Default {
+    fn is_finite(&self) -> bool {
+        true
+    }
+}
+
+impl Number for u8 {}
+
+impl Number for i64 {}
+
+impl Number for f64 {
+    fn is_finite(&self) -> bool {
+        f64::is_finite(*self)
+    }
+}
+
+fn parse_n<T, const N: usize>(args: &[&str], what: &str) -> Result<[T; N], String>
+where
+    T: Number,
+{
+    if args.len() != N {
+        return Err(format!(
+            "{what} expects {N} numbers, got {} ({args:?})",
+            args.len()
+        ));
+    }
+    let mut out = [T::default(); N];
+    for (o, a) in out.iter_mut().zip(args) {
+        *o = a
+            .parse()
+            .ok()
+            .filter(T::is_finite)
+            .ok_or_else(|| format!("{what}: `{a}` is not a valid number"))?;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::srgb_to_linear, octree::cast_to_hit, test_util::assert_close};
+
+    fn parse_error(source: &str) -> (usize, String) {
+        match Scene::parse(source) {
+            Err(SceneError::Parse { line, message }) => (line, message),
+            Err(e) => panic!("unexpected error {e}"),
+            Ok(_) => panic!("scene should not parse"),
+        }
+    }
+
+    #[test]
+    fn parses_voxels_and_lights() {
+        let scene = Scene::parse(
+            "# comment\n\
+             bounds 0 0 0 16\n\
+             material lamp emission 255 255 255 40\n\
+             voxel 1 2 3 lamp # trailing comment\n\
+             box 4 4 4 5 5 5 rough 10 20 30 255\n\
+             voxel 7 7 7 glass 200 220 255 1.5 0.25\n",
+        )
+        .unwrap();
+        let hit = cast_to_hit(
+            Vec3::new(7.5, 7.5, 0.5),
+            &Vec3::new(0., 0., 1.),
+            &scene.tree,
+        );
+        assert_eq!(
+            hit.voxel,
+            Some(&VoxelMaterial::glass([200, 220, 255], 1.5, 0.25))
+        );
+        let hit = cast_to_hit(
+            Vec3::new(1.5, 2.5, 0.5),
+            &Vec3::new(0., 0., 1.),
+            &scene.tree,
+        );
+        assert_eq!(
+            hit.voxel,
+            Some(&VoxelMaterial::emissive([255, 255, 255], 40))
+        );
+        let mut lights = 0;
+        scene
+            .lights
+            .query(&Vec3::new(1.5, 2.5, 0.5), |_, _| lights += 1);
+        assert_eq!(lights, 1);
+        assert_eq!(scene.lights.emission(&Vec3::newi(1, 2, 3)), Some(40));
+        let hit = cast_to_hit(
+            Vec3::new(4.5, 4.5, 0.5),
+            &Vec3::new(0., 0., 1.),
+            &scene.tree,
+        );
+        assert!((hit.distance - 3.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn lights_follow_voxels() {
+        let lamp = VoxelMaterial::emissive([255, 255, 255], 60);
+        let mut scene = Scene::new(Cube::new(0., 0., 0., 16.));
+        scene.fill_box(Vec3::newi(2, 2, 2), Vec3::newi(4, 4, 4), lamp);
+        scene.insert(Vec3::newi(8, 8, 8), lamp);
+        assert_eq!(scene.lights.len(), 28);
+
+        // overwriting a light with another material or emission
+        scene.insert(Vec3::newi(8, 8, 8), VoxelMaterial::rough([1, 1, 1], 255));
+        scene.insert(Vec3::newi(2, 2, 2), VoxelMaterial::emissive([1, 1, 1], 10));
+        assert_eq!(scene.lights.len(), 27);
+        assert_eq!(scene.lights.emission(&Vec3::newi(2, 2, 2)), Some(10));
+
+        scene.fill_box(
+            Vec3::newi(4, 2, 2),
+            Vec3::newi(6, 4, 4),
+            VoxelMaterial::rough([1, 1, 1], 255),
+        );
+        assert_eq!(scene.lights.len(), 18);
+        scene.remove(Vec3::newi(3, 3, 3));
+        assert_eq!(scene.lights.len(), 17);
+        scene.clear_box(Vec3::newi(0, 0, 0), Vec3::newi(2, 15, 15));
+        assert_eq!(scene.lights.len(), 8);
+
+        // voxels outside of the bounds don't exist, neither do their lights
+        scene.insert(Vec3::newi(20, 3, 3), lamp);
+        scene.insert(Vec3::newi(-1, 3, 3), lamp);
+        assert_eq!(scene.lights.len(), 8);
+        scene.fill_box(Vec3::newi(14, 14, 14), Vec3::newi(17, 17, 17), lamp);
+        assert_eq!(scene.lights.len(), 16);
+
+        // every light left is an emissive voxel and the other way round
+        for x in 0..16 {
+            for y in 0..16 {
+                for z in 0..16 {
+                    let p = Vec3::newi(x, y, z);
+                    // a ray starting inside of a voxel hits it right away
+                    let hit = cast_to_hit(p.voxel_center(), &Vec3::new(0., 0., 1.), &scene.tree);
+                    let emissive = matches!(hit.voxel, Some(VoxelMaterial::Emission { .. }))
+                        && hit.distance == 0.;
+                    assert_eq!(scene.lights.emission(&p).is_some(), emissive, "{p:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parses_sky() {
+        let up = Vec3::new(0., 0., -1.);
+        let scene = Scene::parse("sky color 255 0 51 2\n").unwrap();
+        assert_close(
+            &scene.environment.radiance(&up),
+            &Vec3::new(2., 0., 2. * srgb_to_linear(51)),
+        );
+        let scene = Scene::parse(
+            "sky gradient 0 0 255 255 255 255 0 0 0 1\n\
+             sky sun 0 0 -1 10 255 255 255 100\n",
+        )
+        .unwrap();
+        assert_close(
+            &scene.environment.radiance(&up),
+            &Vec3::new(100., 100., 101.),
+        );
+        let horizon = scene.environment.radiance(&Vec3::new(1., 0., 0.));
+        assert_close(&horizon, &Vec3::new(1., 1., 1.));
+        assert!(Scene::parse("bounds 0 0 0 16\n")
+            .unwrap()
+            .environment
+            .is_black());
+
+        assert_eq!(
+            parse_error("sky sun 0 0 -1 1 1 1 1 1").1,
+            "sky sun needs a sky gradient before it"
+        );
+        assert_eq!(parse_error("sky color 1 1 1.5 1").0, 1);
+        assert_eq!(parse_error("\nsky image missing.hdr 1").0, 2);
+    }
+
+    #[test]
+    fn parses_lights() {
+        let scene = Scene::parse(
+            "light point 1 2 3 255 0 0 10\n\
+             bounds 0 0 0 16\n\
+             light spot 1 1 -5 0 0 2 20 30 255 255 255 1\n\
+             light sun 0 0 -1 0.5 255 255 255 3\n",
+        )
+        .unwrap();
+        let lights = scene.lights.analytic();
+        assert_eq!(lights.len(), 3);
+        assert_eq!(
+            lights[0],
+            Light::Point {
+                position: Vec3::new(1., 2., 3.),
+                intensity: Vec3::new(10., 0., 0.),
+            }
+        );
+        let Light::Spot {
+            direction, outer, ..
+        } = lights[1]
+        else {
+            panic!("expected a spot light, got {:?}", lights[1]);
+        };
+        assert_close(&direction, &Vec3::new(0., 0., 1.));
+        assert!((outer - 30_f64.to_radians()).abs() < 1e-12);
+        assert!(matches!(lights[2], Light::Sun { .. }));
+
+        assert_eq!(parse_error("light spot 0 0 0 0 0 1 40 30 1 1 1 1").0, 1);
+        assert_eq!(
+            parse_error("light point 0 0 0 1 1 1 -1").1,
+            "strength -1 must not be negative"
+        );
+        assert_eq!(
+            parse_error("light sun 0 0 0 1 1 1 1 1").1,
+            "direction must not be zero"
+        );
+        // both kinds of sun share their limits
+        assert_eq!(
+            parse_error("light sun 0 0 -1 0 1 1 1 1").1,
+            "sun angle 0 must be between 0 and 90 degrees"
+        );
+        assert_eq!(
+            parse_error("sky gradient 1 1 1 1 1 1 1 1 1 1\nsky sun 0 0 -1 0 1 1 1 1").1,
+            "sun angle 0 must be between 0 and 90 degrees"
+        );
+
+        let scene = Scene::parse("light_limits 0.5 2\nbounds 0 0 0 16\n").unwrap();
+        assert_eq!(scene.lights.limits(), LightLimits::new(0.5, 2.).unwrap());
+        assert_eq!(
+            parse_error("light_limits 0.1 -1").1,
+            "light limit -1 must not be negative"
+        );
+        assert_eq!(
+            parse_error("light_limits 0.1 inf").1,
+            "light_limits: `inf` is not a valid number"
+        );
+        assert_eq!(
+            parse_error("light_limits NaN 0.1").1,
+            "light_limits: `NaN` is not a valid number"
+        );
+        assert_eq!(parse_error("\nlight_limits 0.1\n").0, 2);
+    }
+
+    #[test]
+    fn reports_line_of_malformed_input() {
+        assert_eq!(parse_error("\n\nvoxel 1 2\n").0, 3);
+        assert_eq!(parse_error("voxel 1 2 x rough 1 1 1 1").0, 1);
+        assert_eq!(
+            parse_error("voxel 1 2 3 missing").1,
+            "unknown material `missing`"
+        );
+        assert_eq!(parse_error("voxel 1 2 3 rough 1 1 1 300").0, 1);
+        assert_eq!(
+            parse_error("voxel 1 2 3 glass 1 1 1 0.5 1").1,
+            "glass ior 0.5 must be at least 1"
+        );
+        assert_eq!(parse_error("bounds 0 0 0 12").0, 1);
+        assert_eq!(
+            parse_error("voxel 1 1 1 rough 1 1 1 1\nbounds 0 0 0 16").0,
+            2
+        );
+        assert_eq!(parse_error("voxel 100 0 0 rough 1 1 1 1").0, 1);
+        assert_eq!(
+            parse_error("bounds 0.5 0 0 16").1,
+            "bounds corner 0.5 0 0 must be whole numbers"
+        );
+        // numbers which are not finite would only show up as NaN pixels
+        assert_eq!(
+            parse_error("\ncamera nan 0 -5 0 0 0").1,
+            "camera: `nan` is not a valid number"
+        );
+        assert_eq!(parse_error("\ncamera nan 0 -5 0 0 0").0, 2);
+        assert_eq!(
+            parse_error("orthographic NaN").1,
+            "orthographic: `NaN` is not a valid number"
+        );
+        assert_eq!(
+            parse_error("light point inf 0 0 1 1 1 1").1,
+            "light point: `inf` is not a valid number"
+        );
+        assert_eq!(
+            parse_error("teleport 1 2 3").1,
+            "unknown directive `teleport`"
+        );
+    }
+}
@@ -0,0 +1,30 @@
+use crate::{math::Vec3, sampling::Rng};
+
+pub const EPS: f64 = 1e-9;
+pub const PI_2: f64 = std::f64::consts::FRAC_PI_2;
+
+pub fn assert_close(a: &Vec3, b: &Vec3) {
+    assert!(a.sub(b).len() < EPS, "{a:?} != {b:?}");
+}
+
+pub fn range(rng: &mut Rng, lo: f64, hi: f64) -> f64 {
+    lo + (hi - lo) * rng.float()
+}
+
+pub fn int(rng: &mut Rng, lo: i64, hi: i64) -> i64 {
+    lo + (rng.next_u64() % (hi - lo) as u64) as i64
+}
+
+pub fn unit_vec(rng: &mut Rng) -> Vec3 {
+    loop {
+        let v = Vec3::new(
+            range(rng, -1., 1.),
+            range(rng, -1., 1.),
+            range(rng, -1., 1.),
+        );
+        let len = v.len();
+        if len > 0.1 && len <= 1. {
+            return v.mulf(1. / len);
+        }
+    }
+}